@@ -1,54 +1,632 @@
-use anyhow::Result;
-use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap};
 
-fn main() -> Result<()> {
-    let input = std::fs::read_to_string("inputs/day10.txt")?;
-
-    let instructions = input.lines().map(|line| {
-        line.split_once(' ')
-            .and_then(|(_, num)| num.parse::<isize>().ok())
-    });
-    let registers = instructions
-        .scan((0, 1), |(pc, x), instruction| {
-            match instruction {
-                Some(addx) => {
-                    *pc += 2;
-                    *x += addx;
+use anyhow::{bail, Result};
+use nom::{
+    branch::alt,
+    bytes::complete::tag,
+    character::complete::{alpha1, alphanumeric1, char, digit1, i64, one_of, space0, space1},
+    combinator::{all_consuming, map, opt, recognize, value},
+    multi::{many0, many0_count},
+    sequence::{pair, preceded, separated_pair, terminated},
+    IResult,
+};
+use thiserror::Error;
+
+/// X is the puzzle's register, W is how wide the sprite is, and A to C are for
+/// anything else
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Register {
+    X,
+    W,
+    A,
+    B,
+    C,
+}
+
+impl Register {
+    fn name(self) -> char {
+        match self {
+            Register::X => 'x',
+            Register::W => 'w',
+            Register::A => 'a',
+            Register::B => 'b',
+            Register::C => 'c',
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Registers([i64; 5]);
+
+impl Default for Registers {
+    /// X starts at 1 and the sprite is 3 pixels wide, like in the puzzle
+    fn default() -> Self {
+        Self([1, 3, 0, 0, 0])
+    }
+}
+
+impl Registers {
+    fn get(&self, register: Register) -> i64 {
+        self.0[register as usize]
+    }
+
+    fn get_mut(&mut self, register: Register) -> &mut i64 {
+        &mut self.0[register as usize]
+    }
+}
+
+/// The puzzle's `noop` and `addx`, plus some extras. Jumps go to the index of an
+/// instruction, or to a label or offset before the program is assembled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Instruction<T = usize> {
+    Noop,
+    Addx(i64),
+    Add(Register, i64),
+    Set(Register, i64),
+    Jmp(T),
+    /// jumps if the register isn't zero
+    Jnz(Register, T),
+}
+
+impl<T> Instruction<T> {
+    /// How many cycles the instruction takes to finish
+    fn cycles(&self) -> usize {
+        match self {
+            Instruction::Addx(_) | Instruction::Add(..) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Swaps the jump target for another sort, or gives up if it can't be swapped
+    fn map_target<U, E>(self, f: impl FnOnce(T) -> Result<U, E>) -> Result<Instruction<U>, E> {
+        Ok(match self {
+            Instruction::Noop => Instruction::Noop,
+            Instruction::Addx(n) => Instruction::Addx(n),
+            Instruction::Add(r, n) => Instruction::Add(r, n),
+            Instruction::Set(r, n) => Instruction::Set(r, n),
+            Instruction::Jmp(target) => Instruction::Jmp(f(target)?),
+            Instruction::Jnz(r, target) => Instruction::Jnz(r, f(target)?),
+        })
+    }
+}
+
+/// Where a jump goes before the program is assembled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target<'a> {
+    Label(&'a str),
+    /// relative to the jump itself
+    Offset(i64),
+}
+
+fn parse_register(input: &str) -> IResult<&str, Register> {
+    alt((
+        value(Register::X, tag("x")),
+        value(Register::W, tag("w")),
+        value(Register::A, tag("a")),
+        value(Register::B, tag("b")),
+        value(Register::C, tag("c")),
+    ))(input)
+}
+
+fn parse_label(input: &str) -> IResult<&str, &str> {
+    recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0_count(alt((alphanumeric1, tag("_")))),
+    ))(input)
+}
+
+fn parse_target(input: &str) -> IResult<&str, Target<'_>> {
+    alt((
+        map(parse_label, Target::Label),
+        map(recognize(pair(one_of("+-"), digit1)), |offset: &str| {
+            Target::Offset(offset.parse().unwrap())
+        }),
+    ))(input)
+}
+
+fn parse_instruction(input: &str) -> IResult<&str, Instruction<Target<'_>>> {
+    alt((
+        value(Instruction::Noop, tag("noop")),
+        map(preceded(tag("addx "), i64), Instruction::Addx),
+        map(
+            preceded(tag("add "), separated_pair(parse_register, space1, i64)),
+            |(r, n)| Instruction::Add(r, n),
+        ),
+        map(
+            preceded(tag("set "), separated_pair(parse_register, space1, i64)),
+            |(r, n)| Instruction::Set(r, n),
+        ),
+        map(preceded(tag("jmp "), parse_target), Instruction::Jmp),
+        map(
+            preceded(
+                tag("jnz "),
+                separated_pair(parse_register, space1, parse_target),
+            ),
+            |(r, target)| Instruction::Jnz(r, target),
+        ),
+    ))(input)
+}
+
+/// A line of assembly: any number of `label:`s, then maybe an instruction
+fn parse_line(input: &str) -> IResult<&str, (Vec<&str>, Option<Instruction<Target<'_>>>)> {
+    all_consuming(pair(
+        many0(terminated(parse_label, pair(char(':'), space0))),
+        opt(parse_instruction),
+    ))(input)
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum AsmError {
+    #[error("line {0}: can't make sense of this")]
+    Syntax(usize),
+    #[error("line {line}: {label:?} is defined twice")]
+    DuplicateLabel { line: usize, label: String },
+    #[error("line {line}: there's no label called {label:?}")]
+    UnknownLabel { line: usize, label: String },
+    #[error("line {0}: jumps before the start of the program")]
+    OutOfRange(usize),
+}
+
+/// Assembles a program, one instruction a line. Anything after a `;` is a comment, and
+/// jumps go to a label or to a signed offset like `-2`
+fn assemble(source: &str) -> Result<Vec<Instruction>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut unresolved = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let code = line.split(';').next().unwrap().trim();
+        let (_, (names, instruction)) = parse_line(code).map_err(|_| AsmError::Syntax(i + 1))?;
+        for name in names {
+            if labels.insert(name, unresolved.len()).is_some() {
+                return Err(AsmError::DuplicateLabel {
+                    line: i + 1,
+                    label: name.to_owned(),
+                });
+            }
+        }
+        unresolved.extend(instruction.map(|instruction| (i + 1, instruction)));
+    }
+    unresolved
+        .into_iter()
+        .enumerate()
+        .map(|(index, (line, instruction))| {
+            instruction.map_target(|target| match target {
+                Target::Label(label) => {
+                    labels
+                        .get(label)
+                        .copied()
+                        .ok_or_else(|| AsmError::UnknownLabel {
+                            line,
+                            label: label.to_owned(),
+                        })
                 }
-                None => *pc += 1,
-            };
-            Some((*pc, *x))
+                Target::Offset(offset) => index
+                    .checked_add_signed(offset as isize)
+                    .ok_or(AsmError::OutOfRange(line)),
+            })
+        })
+        .collect()
+}
+
+/// Turns a program back into assembly that assembles to the same thing, with a label
+/// at every place something jumps to
+fn disassemble(program: &[Instruction]) -> String {
+    let targets = program
+        .iter()
+        .filter_map(|instruction| match instruction {
+            Instruction::Jmp(target) | Instruction::Jnz(_, target) => Some(*target),
+            _ => None,
         })
-        .collect_vec();
+        .collect::<BTreeSet<_>>();
+    let mut lines = vec![];
+    for index in 0..=program.len() {
+        if targets.contains(&index) {
+            lines.push(format!("l{index}:"));
+        }
+        let Some(instruction) = program.get(index) else {
+            break;
+        };
+        lines.push(match *instruction {
+            Instruction::Noop => "    noop".to_owned(),
+            Instruction::Addx(n) => format!("    addx {n}"),
+            Instruction::Add(r, n) => format!("    add {} {n}", r.name()),
+            Instruction::Set(r, n) => format!("    set {} {n}", r.name()),
+            Instruction::Jmp(target) => format!("    jmp l{target}"),
+            Instruction::Jnz(r, target) => format!("    jnz {} l{target}", r.name()),
+        });
+    }
+    lines.join("\n")
+}
+
+/// Gets a look at the machine during every cycle, before any instruction finishing at
+/// the end of that cycle has changed anything
+trait Observer {
+    fn during(&mut self, cycle: usize, registers: &Registers);
+}
+
+/// Runs a program one cycle at a time
+#[derive(Debug, Clone)]
+struct Cpu<'a> {
+    program: &'a [Instruction],
+    /// the instruction being run
+    pc: usize,
+    /// how many cycles the current instruction has already taken
+    busy: usize,
+    /// the cycle that's about to start, counting from 1
+    cycle: usize,
+    registers: Registers,
+}
 
-    let mut next_pc_value = 20;
-    let mut result = 0;
-    for (i, (pc, _)) in registers.iter().enumerate() {
-        if *pc >= next_pc_value {
-            result += next_pc_value * registers[i - 1].1;
-            next_pc_value += 40;
+impl<'a> Cpu<'a> {
+    fn new(program: &'a [Instruction]) -> Self {
+        Self {
+            program,
+            pc: 0,
+            busy: 0,
+            cycle: 1,
+            registers: Registers::default(),
         }
     }
-    println!("10.1 {result}");
 
-    let mut cycles = registers.into_iter().peekable();
-    let mut x = 1_isize;
-    let mut display = String::with_capacity(240);
-    for pc in 0..=240_isize {
-        if let Some((next_pc, _)) = cycles.peek() {
-            if *next_pc == pc {
-                x = cycles.next().unwrap().1;
+    /// Runs a single cycle, showing it to every observer. Returns false without doing
+    /// anything once the program has finished
+    fn step(&mut self, observers: &mut [&mut dyn Observer]) -> bool {
+        let Some(&instruction) = self.program.get(self.pc) else {
+            return false;
+        };
+        for observer in observers.iter_mut() {
+            observer.during(self.cycle, &self.registers);
+        }
+        self.cycle += 1;
+        self.busy += 1;
+        if self.busy == instruction.cycles() {
+            self.busy = 0;
+            self.pc += 1;
+            match instruction {
+                Instruction::Noop => {}
+                Instruction::Addx(n) => *self.registers.get_mut(Register::X) += n,
+                Instruction::Add(r, n) => *self.registers.get_mut(r) += n,
+                Instruction::Set(r, n) => *self.registers.get_mut(r) = n,
+                Instruction::Jmp(target) => self.pc = target,
+                Instruction::Jnz(r, target) => {
+                    if self.registers.get(r) != 0 {
+                        self.pc = target;
+                    }
+                }
             }
         }
-        if (pc % 40) == 0 {
-            display.push('\n');
+        true
+    }
+
+    fn run(&mut self, observers: &mut [&mut dyn Observer]) {
+        while self.step(observers) {}
+    }
+
+    /// Like `run`, but gives up after `cycles` cycles in case the program never ends
+    fn run_for(&mut self, observers: &mut [&mut dyn Observer], cycles: usize) {
+        for _ in 0..cycles {
+            if !self.step(observers) {
+                break;
+            }
         }
-        if (pc % 40).abs_diff(x) <= 1 {
-            display.push('#');
-        } else {
-            display.push('.');
+    }
+}
+
+/// Adds up cycle × X during the 20th cycle and every 40th one after it
+#[derive(Debug, Default)]
+struct SignalStrength {
+    total: i64,
+}
+
+impl Observer for SignalStrength {
+    fn during(&mut self, cycle: usize, registers: &Registers) {
+        if cycle % 40 == 20 {
+            self.total += cycle as i64 * registers.get(Register::X);
+        }
+    }
+}
+
+/// Lights a pixel whenever the sprite covers the pixel being drawn. The sprite is W
+/// pixels wide and centred on X, leaning left when W is even
+#[derive(Debug)]
+struct Crt {
+    width: usize,
+    pixels: Vec<bool>,
+}
+
+impl Crt {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    fn render(&self) -> String {
+        self.pixels
+            .chunks(self.width)
+            .map(|row| row.iter().map(|&lit| if lit { '#' } else { '.' }).collect())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Observer for Crt {
+    fn during(&mut self, cycle: usize, registers: &Registers) {
+        let position = cycle - 1;
+        let (x, width) = (registers.get(Register::X), registers.get(Register::W));
+        if let Some(pixel) = self.pixels.get_mut(position) {
+            let left = x - width / 2;
+            *pixel = (left..left + width).contains(&((position % self.width) as i64));
+        }
+    }
+}
+
+/// The capital letters the puzzles spell out, each 4 pixels wide and 6 tall, read row by
+/// row
+/// The Y is a pixel wider than the rest, reaching into the blank column after it
+const FONT: [(char, &str); 18] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('I', ".###..#...#...#...#..###"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Y', "#...#....#.#..#...#...#."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum OcrError {
+    #[error("the screen is {0} pixels tall but letters are {GLYPH_HEIGHT}")]
+    WrongHeight(usize),
+    #[error("couldn't read the letters at {positions:?}, got {text:?}")]
+    Unrecognised { text: String, positions: Vec<usize> },
+}
+
+/// Reads the letters off the screen. Letters sit in 5 pixel wide cells with a blank
+/// column between them, and blank cells come out as spaces
+fn ocr(crt: &Crt) -> Result<String, OcrError> {
+    let height = crt.pixels.len() / crt.width;
+    if height != GLYPH_HEIGHT {
+        return Err(OcrError::WrongHeight(height));
+    }
+    let mut text = String::new();
+    let mut positions = vec![];
+    for (i, left) in (0..crt.width).step_by(GLYPH_WIDTH + 1).enumerate() {
+        let glyph = (0..GLYPH_HEIGHT)
+            .flat_map(|y| (left..left + GLYPH_WIDTH).map(move |x| (x, y)))
+            .map(|(x, y)| x < crt.width && crt.pixels[y * crt.width + x])
+            .map(|lit| if lit { '#' } else { '.' })
+            .collect::<String>();
+        match FONT.iter().find(|(_, pixels)| *pixels == glyph) {
+            Some(&(letter, _)) => text.push(letter),
+            None if !glyph.contains('#') => text.push(' '),
+            None => {
+                text.push('?');
+                positions.push(i);
+            }
+        }
+    }
+    let text = text.trim_end().to_owned();
+    match positions.is_empty() {
+        true => Ok(text),
+        false => Err(OcrError::Unrecognised { text, positions }),
+    }
+}
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let (path, disassembling, cycles) = match args[..] {
+        [] => ("inputs/day10.txt", false, None),
+        ["--disassemble", path] => (path, true, None),
+        ["--run", path] => (path, false, Some(240)),
+        ["--run", path, "--cycles", cycles] => (path, false, Some(cycles.parse()?)),
+        _ => bail!("usage: day10 [--disassemble FILE | --run FILE [--cycles N]]"),
+    };
+    let program = assemble(&std::fs::read_to_string(path)?)?;
+    if disassembling {
+        println!("{}", disassemble(&program));
+        return Ok(());
+    }
+
+    let mut signal = SignalStrength::default();
+    let mut crt = Crt::new(40, 6);
+    let mut cpu = Cpu::new(&program);
+    match cycles {
+        Some(cycles) => cpu.run_for(&mut [&mut signal, &mut crt], cycles),
+        None => cpu.run(&mut [&mut signal, &mut crt]),
+    }
+    println!("10.1 {}", signal.total);
+    match ocr(&crt) {
+        Ok(letters) => println!("10.2 {letters}"),
+        Err(e) => {
+            eprintln!("{e}");
+            println!("10.2 \n{}", crt.render());
         }
     }
-    println!("10.2 {display}");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Trace(Vec<(usize, i64)>);
+    impl Observer for Trace {
+        fn during(&mut self, cycle: usize, registers: &Registers) {
+            self.0.push((cycle, registers.get(Register::X)));
+        }
+    }
+
+    fn test_program() -> Vec<Instruction> {
+        let input = std::fs::read_to_string("test_inputs/day10.txt").unwrap();
+        assemble(&input).unwrap()
+    }
+
+    #[test]
+    fn test_part1() {
+        let program = test_program();
+        let mut signal = SignalStrength::default();
+        Cpu::new(&program).run(&mut [&mut signal]);
+        assert_eq!(signal.total, 13140);
+    }
+
+    #[test]
+    fn test_part2() {
+        let program = test_program();
+        let mut crt = Crt::new(40, 6);
+        Cpu::new(&program).run(&mut [&mut crt]);
+        assert_eq!(
+            crt.render(),
+            "##..##..##..##..##..##..##..##..##..##..
+###...###...###...###...###...###...###.
+####....####....####....####....####....
+#####.....#####.....#####.....#####.....
+######......######......######......####
+#######.......#######.......#######....."
+        );
+    }
+
+    #[test]
+    fn test_cycles() {
+        let program = assemble("noop\naddx 3\naddx -5").unwrap();
+        let mut trace = Trace(vec![]);
+        let mut cpu = Cpu::new(&program);
+        cpu.run(&mut [&mut trace]);
+        assert_eq!(trace.0, vec![(1, 1), (2, 1), (3, 1), (4, 4), (5, 4)]);
+        assert_eq!((cpu.cycle, cpu.registers.get(Register::X)), (6, -1));
+        assert!(!cpu.step(&mut []));
+        assert_eq!(assemble("mul x 4"), Err(AsmError::Syntax(1)));
+    }
+
+    #[test]
+    fn test_ocr() {
+        let mut crt = Crt::new(40, 6);
+        for (i, letter) in "EPJBRKAH".chars().enumerate() {
+            let (_, pixels) = FONT.iter().find(|&&(c, _)| c == letter).unwrap();
+            for (j, pixel) in pixels.chars().enumerate() {
+                let (x, y) = (i * 5 + j % 4, j / 4);
+                crt.pixels[y * 40 + x] = pixel == '#';
+            }
+        }
+        assert_eq!(ocr(&crt), Ok("EPJBRKAH".to_owned()));
+
+        // the whole alphabet, with the Y's right edge drawn in too
+        let letters = FONT.iter().map(|&(c, _)| c).collect::<String>();
+        assert_eq!(letters, "ABCEFGHIJKLOPRSUYZ");
+        let width = FONT.len() * 5;
+        let mut crt = Crt::new(width, 6);
+        for (i, (letter, pixels)) in FONT.iter().enumerate() {
+            for (j, pixel) in pixels.chars().enumerate() {
+                let (x, y) = (i * 5 + j % 4, j / 4);
+                crt.pixels[y * width + x] = pixel == '#';
+            }
+            if *letter == 'Y' {
+                crt.pixels[i * 5 + 4] = true;
+                crt.pixels[width + i * 5 + 4] = true;
+            }
+        }
+        assert_eq!(ocr(&crt), Ok(letters));
+
+        // the test program draws stripes rather than letters
+        let program = test_program();
+        let mut crt = Crt::new(40, 6);
+        Cpu::new(&program).run(&mut [&mut crt]);
+        let Err(OcrError::Unrecognised { text, positions }) = ocr(&crt) else {
+            panic!("stripes aren't letters");
+        };
+        assert_eq!(text, "????????");
+        assert_eq!(positions, (0..8).collect::<Vec<_>>());
+
+        assert_eq!(ocr(&Crt::new(40, 5)), Err(OcrError::WrongHeight(5)));
+        assert_eq!(ocr(&Crt::new(40, 6)), Ok(String::new()));
+    }
+
+    #[test]
+    fn test_assembler() {
+        let source = "
+            ; adds 2 to x three times
+            start:
+                set a 3
+            loop: add x 2   ; takes two cycles
+                add a -1
+                jnz a loop
+                jmp +1      ; straight to the end
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program[0], Instruction::Set(Register::A, 3));
+        assert_eq!(program[3], Instruction::Jnz(Register::A, 1));
+        assert_eq!(program[4], Instruction::Jmp(5));
+
+        let disassembled = disassemble(&program);
+        assert_eq!(
+            disassembled,
+            "    set a 3
+l1:
+    add x 2
+    add a -1
+    jnz a l1
+    jmp l5
+l5:"
+        );
+        assert_eq!(assemble(&disassembled).unwrap(), program);
+
+        let mut cpu = Cpu::new(&program);
+        cpu.run(&mut []);
+        assert_eq!(cpu.registers.get(Register::X), 7);
+        assert_eq!(cpu.registers.get(Register::A), 0);
+        assert_eq!(cpu.cycle, 1 + 1 + 3 * (2 + 2 + 1) + 1);
+
+        assert_eq!(
+            assemble("a:\na: noop"),
+            Err(AsmError::DuplicateLabel {
+                line: 2,
+                label: "a".into()
+            })
+        );
+        assert_eq!(
+            assemble("noop\njnz x nowhere"),
+            Err(AsmError::UnknownLabel {
+                line: 2,
+                label: "nowhere".into()
+            })
+        );
+        assert_eq!(assemble("jmp -1"), Err(AsmError::OutOfRange(1)));
+    }
+
+    #[test]
+    fn test_extended_machine() {
+        // loops forever, moving the sprite along with the beam
+        let program = assemble("set w 1\nset x 1\nloop: add x 1\njmp loop").unwrap();
+        let mut trace = Trace(vec![]);
+        let mut crt = Crt::new(40, 6);
+        let mut cpu = Cpu::new(&program);
+        cpu.run_for(&mut [&mut trace, &mut crt], 240);
+        assert_eq!(cpu.cycle, 241);
+        assert_eq!(
+            trace.0[..6],
+            [(1, 1), (2, 1), (3, 1), (4, 1), (5, 2), (6, 2)]
+        );
+        assert_eq!(&crt.render()[..10], "##........");
+
+        // a sprite as wide as the screen lights everything
+        let program = assemble("set w 80\nloop: jmp loop").unwrap();
+        let mut crt = Crt::new(40, 6);
+        Cpu::new(&program).run_for(&mut [&mut crt], 240);
+        assert!(crt.pixels[1..].iter().all(|&lit| lit));
+    }
+}
@@ -1,48 +1,52 @@
-use std::collections::VecDeque;
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    num::NonZeroUsize,
+};
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::multispace1,
-    combinator::{map, map_res},
-    multi::separated_list1,
-    sequence::{delimited, preceded, separated_pair, tuple},
+    character::complete::{char, multispace1, space0},
+    combinator::{map, map_res, value, verify},
+    multi::{fold_many0, separated_list1},
+    sequence::{delimited, pair, preceded, tuple},
     IResult,
 };
-
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-enum Variable {
-    Old,
-    Const(usize),
-}
-impl Variable {
-    fn get(&self, input: usize) -> usize {
-        match self {
-            Variable::Old => input,
-            Variable::Const(x) => *x,
-        }
-    }
-}
+use thiserror::Error;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum Op {
     Mul,
+    Div,
     Add,
+    Sub,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-struct MonkeyEquation {
-    op: Op,
-    input: Variable,
+/// The right hand side of a monkey's operation
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Old,
+    Const(usize),
+    BinOp(Op, Box<Expr>, Box<Expr>),
 }
-impl MonkeyEquation {
-    fn apply(&self, old: usize) -> usize {
-        let snd_term = self.input.get(old);
-        match self.op {
-            Op::Mul => old * snd_term,
-            Op::Add => old + snd_term,
-        }
+impl Expr {
+    fn apply<P: WorryPolicy>(&self, policy: &P, old: &P::Worry) -> Result<P::Worry, WorryError> {
+        Ok(match self {
+            Expr::Old => old.clone(),
+            Expr::Const(x) => policy.worry(*x),
+            Expr::BinOp(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.apply(policy, old)?, rhs.apply(policy, old)?);
+                match op {
+                    Op::Mul => policy.mul(&lhs, &rhs)?,
+                    Op::Div => policy.div(&lhs, &rhs)?,
+                    Op::Add => policy.add(&lhs, &rhs)?,
+                    Op::Sub => policy.sub(&lhs, &rhs)?,
+                }
+            }
+        })
     }
 }
 
@@ -57,7 +61,7 @@ struct MonkeyTest {
 struct Monkey {
     id: usize,
     items: VecDeque<usize>,
-    operation: MonkeyEquation,
+    operation: Expr,
     test: MonkeyTest,
 }
 
@@ -73,24 +77,52 @@ fn starting_items(input: &str) -> IResult<&str, Vec<usize>> {
     preceded(tag("Starting items: "), separated_list1(tag(", "), digit1))(input)
 }
 
-fn variable(input: &str) -> IResult<&str, Variable> {
+fn factor(input: &str) -> IResult<&str, Expr> {
     alt((
-        map(tag("old"), |_| Variable::Old),
-        map(digit1, Variable::Const),
+        map(tag("old"), |_| Expr::Old),
+        map(digit1, Expr::Const),
+        delimited(pair(tag("("), space0), expr, pair(space0, tag(")"))),
     ))(input)
 }
+
 fn op(input: &str) -> IResult<&str, Op> {
-    alt((map(tag("+"), |_| Op::Add), map(tag("*"), |_| Op::Mul)))(input)
+    alt((
+        value(Op::Mul, char('*')),
+        value(Op::Div, char('/')),
+        value(Op::Add, char('+')),
+        value(Op::Sub, char('-')),
+    ))(input)
 }
 
-fn operation(input: &str) -> IResult<&str, MonkeyEquation> {
-    map(
-        preceded(
-            tag("Operation: new = old "),
-            separated_pair(op, tag(" "), variable),
-        ),
-        |(op, input)| MonkeyEquation { op, input },
-    )(input)
+/// Left-associative chain of `operand`s joined by any of the operators `ops`, which
+/// all bind equally tightly
+fn chain<'a>(
+    ops: [Op; 2],
+    operand: fn(&'a str) -> IResult<&'a str, Expr>,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Expr> {
+    move |input| {
+        let (input, first) = operand(input)?;
+        fold_many0(
+            pair(
+                delimited(space0, verify(op, |op| ops.contains(op)), space0),
+                operand,
+            ),
+            move || first.clone(),
+            |lhs, (op, rhs)| Expr::BinOp(op, Box::new(lhs), Box::new(rhs)),
+        )(input)
+    }
+}
+
+fn term(input: &str) -> IResult<&str, Expr> {
+    chain([Op::Mul, Op::Div], factor)(input)
+}
+
+fn expr(input: &str) -> IResult<&str, Expr> {
+    chain([Op::Add, Op::Sub], term)(input)
+}
+
+fn operation(input: &str) -> IResult<&str, Expr> {
+    preceded(tag("Operation: new = "), expr)(input)
 }
 
 fn test_header(input: &str) -> IResult<&str, usize> {
@@ -139,64 +171,820 @@ fn monkey(input: &str) -> IResult<&str, Monkey> {
 fn monkeys(input: &str) -> IResult<&str, Vec<Monkey>> {
     separated_list1(multispace1, monkey)(input)
 }
+
+/// An unsigned integer of any size, stored as base 2^32 digits, least significant first
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BigUint(Vec<u32>);
+
+impl From<usize> for BigUint {
+    fn from(mut n: usize) -> Self {
+        let mut digits = vec![];
+        while n > 0 {
+            digits.push(n as u32);
+            n = (n as u64 >> 32) as usize;
+        }
+        Self(digits)
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl BigUint {
+    /// Drops leading zeros, so every number has just the one representation
+    fn from_digits(mut digits: Vec<u32>) -> Self {
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        Self(digits)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        let mut digits = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let sum =
+                *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64 + carry;
+            digits.push(sum as u32);
+            carry = sum >> 32;
+        }
+        if carry > 0 {
+            digits.push(carry as u32);
+        }
+        Self(digits)
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let mut digits = vec![0u32; self.0.len() + other.0.len()];
+        for (i, &a) in self.0.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.0.iter().enumerate() {
+                let product = a as u64 * b as u64 + digits[i + j] as u64 + carry;
+                digits[i + j] = product as u32;
+                carry = product >> 32;
+            }
+            digits[i + other.0.len()] = carry as u32;
+        }
+        Self::from_digits(digits)
+    }
+
+    fn checked_sub(&self, other: &Self) -> Option<Self> {
+        if self < other {
+            return None;
+        }
+        let mut borrow = 0;
+        let digits = self
+            .0
+            .iter()
+            .enumerate()
+            .map(|(i, &digit)| {
+                let (digit, under) = digit.overflowing_sub(*other.0.get(i).unwrap_or(&0));
+                let (digit, under_again) = digit.overflowing_sub(borrow);
+                borrow = (under || under_again) as u32;
+                digit
+            })
+            .collect();
+        Some(Self::from_digits(digits))
+    }
+
+    /// Long division, one bit of the quotient at a time
+    fn div(&self, divisor: &Self) -> Self {
+        let mut quotient = vec![0u32; self.0.len()];
+        let mut rem = Self(vec![]);
+        for bit in (0..self.0.len() * 32).rev() {
+            rem = rem.add(&rem);
+            if self.0[bit / 32] >> (bit % 32) & 1 == 1 {
+                rem = rem.add(&Self(vec![1]));
+            }
+            if rem >= *divisor {
+                rem = rem.checked_sub(divisor).unwrap();
+                quotient[bit / 32] |= 1 << (bit % 32);
+            }
+        }
+        Self::from_digits(quotient)
+    }
+
+    fn div_rem(&self, divisor: usize) -> (Self, usize) {
+        let divisor = divisor as u128;
+        let mut rem = 0;
+        let mut digits = vec![0; self.0.len()];
+        for (quotient, &digit) in digits.iter_mut().zip(&self.0).rev() {
+            let n = (rem << 32) | digit as u128;
+            *quotient = (n / divisor) as u32;
+            rem = n % divisor;
+        }
+        (Self::from_digits(digits), rem as usize)
+    }
+
+    fn rem(&self, divisor: usize) -> usize {
+        self.div_rem(divisor).1
+    }
+}
+
+impl Display for BigUint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut chunks = vec![];
+        let mut n = self.clone();
+        while !n.is_zero() {
+            let (quotient, chunk) = n.div_rem(1_000_000_000);
+            chunks.push(chunk);
+            n = quotient;
+        }
+        match chunks.pop() {
+            None => write!(f, "0"),
+            Some(first) => {
+                write!(f, "{first}")?;
+                chunks
+                    .iter()
+                    .rev()
+                    .try_for_each(|chunk| write!(f, "{chunk:09}"))
+            }
+        }
+    }
+}
+
+/// Why an operation couldn't work out an item's new worry level
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+enum WorryError {
+    #[error("worry level went below zero")]
+    Negative,
+    #[error("worry level got too big to keep track of")]
+    Overflow,
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("worry levels kept modulo the LCM can't go through `{0}`")]
+    Modular(char),
+}
+
+/// How worry levels are stored and kept in check between inspections
+trait WorryPolicy {
+    type Worry: Clone + Display;
+
+    fn worry(&self, level: usize) -> Self::Worry;
+    fn add(&self, a: &Self::Worry, b: &Self::Worry) -> Result<Self::Worry, WorryError>;
+    fn sub(&self, a: &Self::Worry, b: &Self::Worry) -> Result<Self::Worry, WorryError>;
+    fn mul(&self, a: &Self::Worry, b: &Self::Worry) -> Result<Self::Worry, WorryError>;
+    fn div(&self, a: &Self::Worry, b: &Self::Worry) -> Result<Self::Worry, WorryError>;
+    /// What happens to the worry level once the monkey gets bored with the item
+    fn relieve(&self, worry: Self::Worry) -> Self::Worry;
+    fn divisible(&self, worry: &Self::Worry, by: usize) -> bool;
+}
+
+/// Part 1: worry levels are divided by k after every inspection
+struct DivideBy(NonZeroUsize);
+
+impl DivideBy {
+    fn new(k: usize) -> Option<Self> {
+        NonZeroUsize::new(k).map(Self)
+    }
+}
+
+impl WorryPolicy for DivideBy {
+    type Worry = usize;
+
+    fn worry(&self, level: usize) -> usize {
+        level
+    }
+    fn add(&self, a: &usize, b: &usize) -> Result<usize, WorryError> {
+        a.checked_add(*b).ok_or(WorryError::Overflow)
+    }
+    fn sub(&self, a: &usize, b: &usize) -> Result<usize, WorryError> {
+        a.checked_sub(*b).ok_or(WorryError::Negative)
+    }
+    fn mul(&self, a: &usize, b: &usize) -> Result<usize, WorryError> {
+        a.checked_mul(*b).ok_or(WorryError::Overflow)
+    }
+    fn div(&self, a: &usize, b: &usize) -> Result<usize, WorryError> {
+        a.checked_div(*b).ok_or(WorryError::DivisionByZero)
+    }
+    fn relieve(&self, worry: usize) -> usize {
+        worry / self.0
+    }
+    fn divisible(&self, worry: &usize, by: usize) -> bool {
+        worry.is_multiple_of(by)
+    }
+}
+
+/// Part 2: worry levels are kept modulo the lowest common multiple of every monkey's
+/// divisor, which doesn't change the result of any monkey's test. That only holds for
+/// adding and multiplying: it couldn't tell whether subtracting went below zero, and
+/// there's no dividing a remainder, so both are errors
+struct ModLcm(usize);
+
+impl ModLcm {
+    fn new(monkeys: &[Monkey]) -> Self {
+        let gcd = |mut a: usize, mut b: usize| {
+            while b > 0 {
+                (a, b) = (b, a % b);
+            }
+            a
+        };
+        Self(
+            monkeys
+                .iter()
+                .map(|monkey| monkey.test.divisible_by)
+                .fold(1, |lcm, n| lcm / gcd(lcm, n) * n),
+        )
+    }
+}
+
+impl WorryPolicy for ModLcm {
+    type Worry = usize;
+
+    fn worry(&self, level: usize) -> usize {
+        level % self.0
+    }
+    fn add(&self, a: &usize, b: &usize) -> Result<usize, WorryError> {
+        Ok(((*a as u128 + *b as u128) % self.0 as u128) as usize)
+    }
+    fn sub(&self, _: &usize, _: &usize) -> Result<usize, WorryError> {
+        Err(WorryError::Modular('-'))
+    }
+    fn mul(&self, a: &usize, b: &usize) -> Result<usize, WorryError> {
+        Ok(((*a as u128 * *b as u128) % self.0 as u128) as usize)
+    }
+    fn div(&self, _: &usize, _: &usize) -> Result<usize, WorryError> {
+        Err(WorryError::Modular('/'))
+    }
+    fn relieve(&self, worry: usize) -> usize {
+        worry
+    }
+    fn divisible(&self, worry: &usize, by: usize) -> bool {
+        worry.is_multiple_of(by)
+    }
+}
+
+/// No relief and no overflow: worry levels just keep growing. Squaring doubles their
+/// size each time though, so this only gets through a few dozen rounds
+struct Unbounded;
+
+impl WorryPolicy for Unbounded {
+    type Worry = BigUint;
+
+    fn worry(&self, level: usize) -> BigUint {
+        level.into()
+    }
+    fn add(&self, a: &BigUint, b: &BigUint) -> Result<BigUint, WorryError> {
+        Ok(a.add(b))
+    }
+    fn sub(&self, a: &BigUint, b: &BigUint) -> Result<BigUint, WorryError> {
+        a.checked_sub(b).ok_or(WorryError::Negative)
+    }
+    fn mul(&self, a: &BigUint, b: &BigUint) -> Result<BigUint, WorryError> {
+        Ok(a.mul(b))
+    }
+    fn div(&self, a: &BigUint, b: &BigUint) -> Result<BigUint, WorryError> {
+        match b.is_zero() {
+            true => Err(WorryError::DivisionByZero),
+            false => Ok(a.div(b)),
+        }
+    }
+    fn relieve(&self, worry: BigUint) -> BigUint {
+        worry
+    }
+    fn divisible(&self, worry: &BigUint, by: usize) -> bool {
+        worry.rem(by) == 0
+    }
+}
+
+impl Monkey {
+    /// The item's new worry level, and which monkey it gets thrown to
+    fn inspect<P: WorryPolicy>(
+        &self,
+        policy: &P,
+        item: &P::Worry,
+    ) -> Result<(P::Worry, usize), WorryError> {
+        let worry_level = policy.relieve(self.operation.apply(policy, item)?);
+        let target = match policy.divisible(&worry_level, self.test.divisible_by) {
+            true => self.test.t,
+            false => self.test.f,
+        };
+        Ok((worry_level, target))
+    }
+}
+
+/// Plays keep away. Each monkey's items live in their own queue, so a monkey's turn
+/// just moves worry levels from one queue to the others
+struct Troop<'a, P: WorryPolicy> {
+    monkeys: &'a [Monkey],
+    policy: P,
+    items: Vec<VecDeque<P::Worry>>,
+    inspections: Vec<usize>,
+}
+
+impl<'a, P: WorryPolicy> Troop<'a, P> {
+    fn new(monkeys: &'a [Monkey], policy: P) -> Self {
+        let items = monkeys
+            .iter()
+            .map(|monkey| {
+                monkey
+                    .items
+                    .iter()
+                    .map(|&item| policy.worry(item))
+                    .collect()
+            })
+            .collect();
+        Self {
+            monkeys,
+            policy,
+            items,
+            inspections: vec![0; monkeys.len()],
+        }
+    }
+
+    fn turn(&mut self, i: usize) -> Result<(), WorryError> {
+        let mut items = std::mem::take(&mut self.items[i]);
+        self.inspections[i] += items.len();
+        for item in items.drain(..) {
+            let (worry_level, target) = self.monkeys[i].inspect(&self.policy, &item)?;
+            self.items[target].push_back(worry_level);
+        }
+        // hand the emptied queue back so its allocation gets reused
+        if self.items[i].is_empty() {
+            self.items[i] = items;
+        }
+        Ok(())
+    }
+
+    fn round(&mut self) -> Result<(), WorryError> {
+        (0..self.monkeys.len()).try_for_each(|i| self.turn(i))
+    }
+
+    /// Plays `rounds` more rounds, returning how many items each monkey has inspected
+    /// so far. After an error the troop is left mid-turn
+    fn run(&mut self, rounds: usize) -> Result<&[usize], WorryError> {
+        for _ in 0..rounds {
+            self.round()?;
+        }
+        Ok(&self.inspections)
+    }
+
+    /// The two biggest inspection counts multiplied together
+    fn monkey_business(&self) -> usize {
+        let mut inspections = self.inspections.clone();
+        inspections.sort();
+        inspections.iter().rev().take(2).product()
+    }
+}
+
+/// A monkey picking up an item: in which round, and how worried we were about it
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Inspection<W> {
+    round: usize,
+    monkey: usize,
+    worry: W,
+}
+
+/// Follows one item around. Items never affect each other, so any item can be traced
+/// on its own; the round only moves on when the item gets thrown back to a monkey
+/// whose turn has already passed. The trace ends after the first inspection that fails
+struct ItemTrace<'a, P: WorryPolicy> {
+    monkeys: &'a [Monkey],
+    policy: &'a P,
+    next: Option<Result<Inspection<P::Worry>, WorryError>>,
+}
+
+impl<'a, P: WorryPolicy> ItemTrace<'a, P> {
+    fn new(monkeys: &'a [Monkey], policy: &'a P, monkey: usize, item: usize) -> Self {
+        let worry = policy.worry(item);
+        Self {
+            monkeys,
+            policy,
+            next: Some(Ok(Inspection {
+                round: 1,
+                monkey,
+                worry,
+            })),
+        }
+    }
+}
+
+impl<P: WorryPolicy> Iterator for ItemTrace<'_, P> {
+    type Item = Result<Inspection<P::Worry>, WorryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next.take()?;
+        if let Ok(Inspection {
+            round,
+            monkey,
+            worry,
+        }) = &current
+        {
+            self.next = Some(self.monkeys[*monkey].inspect(self.policy, worry).map(
+                |(worry, target)| Inspection {
+                    round: if target > *monkey { *round } else { round + 1 },
+                    monkey: target,
+                    worry,
+                },
+            ));
+        }
+        Some(current)
+    }
+}
+
+/// Every starting item's inspections over the first `rounds` rounds, in the order the
+/// monkeys are holding them
+fn trace<'a, P: WorryPolicy>(
+    monkeys: &'a [Monkey],
+    policy: &'a P,
+    rounds: usize,
+) -> impl Iterator<Item = Result<Vec<Inspection<P::Worry>>, WorryError>> + 'a {
+    monkeys.iter().enumerate().flat_map(move |(i, monkey)| {
+        monkey.items.iter().map(move |&item| {
+            ItemTrace::new(monkeys, policy, i, item)
+                .take_while(|inspection| !inspection.as_ref().is_ok_and(|i| i.round > rounds))
+                .collect()
+        })
+    })
+}
+
+/// An item's inspections up to the point where a monkey picks it up with a worry level
+/// (mod the LCM) it has already had at that monkey. From then on it goes round the same
+/// loop forever, every `period` rounds
+#[derive(Debug)]
+struct ItemCycle {
+    inspections: Vec<Inspection<usize>>,
+    start: usize,
+    period: usize,
+}
+
+impl ItemCycle {
+    /// There are only so many (monkey, worry) states, so this always finishes
+    fn find(
+        monkeys: &[Monkey],
+        policy: &ModLcm,
+        monkey: usize,
+        item: usize,
+    ) -> Result<Self, WorryError> {
+        let mut seen = HashMap::new();
+        let mut inspections = vec![];
+        for inspection in ItemTrace::new(monkeys, policy, monkey, item) {
+            let inspection = inspection?;
+            let state = (inspection.monkey, inspection.worry);
+            if let Some(&(start, round)) = seen.get(&state) {
+                return Ok(Self {
+                    inspections,
+                    start,
+                    period: inspection.round - round,
+                });
+            }
+            seen.insert(state, (inspections.len(), inspection.round));
+            inspections.push(inspection);
+        }
+        unreachable!("traces only end with an error")
+    }
+
+    /// Adds the inspections this item gets in the first `rounds` rounds
+    fn count(&self, rounds: usize, inspections: &mut [usize]) {
+        for (i, inspection) in self.inspections.iter().enumerate() {
+            if inspection.round > rounds {
+                continue;
+            }
+            inspections[inspection.monkey] += match i < self.start {
+                true => 1,
+                false => (rounds - inspection.round) / self.period + 1,
+            };
+        }
+    }
+}
+
+/// Same counts as running a `Troop` with `ModLcm` for `rounds` rounds, but without the
+/// rounds: each item only has to be followed until it starts going in circles
+fn extrapolate(monkeys: &[Monkey], rounds: usize) -> Result<Vec<usize>, WorryError> {
+    let policy = ModLcm::new(monkeys);
+    let mut inspections = vec![0; monkeys.len()];
+    for (i, monkey) in monkeys.iter().enumerate() {
+        for &item in &monkey.items {
+            ItemCycle::find(monkeys, &policy, i, item)?.count(rounds, &mut inspections);
+        }
+    }
+    Ok(inspections)
+}
+
+fn print_trace<P: WorryPolicy>(
+    monkeys: &[Monkey],
+    policy: &P,
+    rounds: usize,
+) -> Result<(), WorryError> {
+    for (i, inspections) in trace(monkeys, policy, rounds).enumerate() {
+        println!("item {i}:");
+        for Inspection {
+            round,
+            monkey,
+            worry,
+        } in inspections?
+        {
+            println!("  round {round:>5}: monkey {monkey} picks it up at {worry}");
+        }
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let input = std::fs::read_to_string("inputs/day11.txt").unwrap();
     let (remaining, monkeys) = monkeys(&input).unwrap();
     assert_eq!(remaining.trim(), "");
-    let mut part1_monkeys = monkeys.clone();
 
-    let mut inspections = vec![0; monkeys.len()];
-    let mut monkey;
-    for _round in 0..20 {
-        for i in 0..part1_monkeys.len() {
-            monkey = part1_monkeys[i].clone();
-            inspections[i] += monkey.items.len();
-
-            while let Some(item) = monkey.items.pop_front() {
-                let worry_level = monkey.operation.apply(item) / 3;
-                if worry_level % monkey.test.divisible_by == 0 {
-                    part1_monkeys[monkey.test.t].items.push_back(worry_level);
-                } else {
-                    part1_monkeys[monkey.test.f].items.push_back(worry_level);
-                }
-            }
-            part1_monkeys[i] = monkey;
-        }
-    }
-    inspections.sort();
-    println!(
-        "11.1 {:?}",
-        inspections.iter().rev().take(2).product::<usize>()
-    );
-
-    let mut part2_monkeys = monkeys.clone();
-    let factor = monkeys
-        .iter()
-        .map(|monkey| monkey.test.divisible_by)
-        .product::<usize>();
-
-    let mut inspections = vec![0; part2_monkeys.len()];
-    let mut monkey;
-    for _round in 0..10000 {
-        for i in 0..part2_monkeys.len() {
-            monkey = part2_monkeys[i].clone();
-            inspections[i] += monkey.items.len();
-
-            while let Some(item) = monkey.items.pop_front() {
-                let worry_level = monkey.operation.apply(item) % factor;
-                if worry_level % monkey.test.divisible_by == 0 {
-                    part2_monkeys[monkey.test.t].items.push_back(worry_level);
-                } else {
-                    part2_monkeys[monkey.test.f].items.push_back(worry_level);
-                }
+    let mut troop = Troop::new(&monkeys, DivideBy::new(3).unwrap());
+    troop.run(20)?;
+    println!("11.1 {:?}", troop.monkey_business());
+
+    let mut troop = Troop::new(&monkeys, ModLcm::new(&monkeys));
+    troop.run(10000)?;
+    println!("11.2 {:?}", troop.monkey_business());
+
+    // any other number of rounds, and how to keep worry levels down, can be asked for
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let divide_by = |k: &str| -> Result<DivideBy> {
+        DivideBy::new(k.parse()?).context("worry levels can't be divided by 0")
+    };
+    match &args[..] {
+        [] => {}
+        ["trace", rounds, policy] => {
+            let rounds = rounds.parse()?;
+            match *policy {
+                "lcm" => print_trace(&monkeys, &ModLcm::new(&monkeys), rounds)?,
+                "unbounded" => print_trace(&monkeys, &Unbounded, rounds)?,
+                k => print_trace(&monkeys, &divide_by(k)?, rounds)?,
             }
-            part2_monkeys[i] = monkey;
+        }
+        ["extrapolate", rounds] => {
+            let rounds = rounds.parse()?;
+            let mut inspections = extrapolate(&monkeys, rounds)?;
+            println!("inspections after {rounds} rounds: {inspections:?}");
+            inspections.sort();
+            let business: u128 = inspections
+                .iter()
+                .rev()
+                .take(2)
+                .map(|&n| n as u128)
+                .product();
+            println!("monkey business: {business}");
+        }
+        [rounds, policy] => {
+            let rounds = rounds.parse()?;
+            let inspections = match *policy {
+                "lcm" => Troop::new(&monkeys, ModLcm::new(&monkeys))
+                    .run(rounds)?
+                    .to_vec(),
+                "unbounded" => Troop::new(&monkeys, Unbounded).run(rounds)?.to_vec(),
+                k => Troop::new(&monkeys, divide_by(k)?).run(rounds)?.to_vec(),
+            };
+            println!("inspections after {rounds} rounds: {inspections:?}");
+        }
+        _ => {
+            bail!("usage: day11 [[trace] ROUNDS lcm|unbounded|K]\n       day11 extrapolate ROUNDS")
         }
     }
-    inspections.sort();
-    println!(
-        "11.2 {:?}",
-        inspections.iter().rev().take(2).product::<usize>()
-    );
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_monkeys() -> Vec<Monkey> {
+        let input = std::fs::read_to_string("test_inputs/day11.txt").unwrap();
+        monkeys(&input).unwrap().1
+    }
+
+    #[test]
+    fn test_part1() {
+        let monkeys = test_monkeys();
+        let mut troop = Troop::new(&monkeys, DivideBy::new(3).unwrap());
+        assert_eq!(troop.run(20).unwrap(), [101, 95, 7, 105]);
+        assert_eq!(troop.monkey_business(), 10605);
+
+        // without enough relief worry levels outgrow a usize instead of wrapping round
+        let mut troop = Troop::new(&monkeys, DivideBy::new(2).unwrap());
+        assert_eq!(troop.run(10000), Err(WorryError::Overflow));
+        assert!(DivideBy::new(0).is_none());
+    }
+
+    #[test]
+    fn test_part2() {
+        let monkeys = test_monkeys();
+        let mut troop = Troop::new(&monkeys, ModLcm::new(&monkeys));
+        assert_eq!(troop.run(1).unwrap(), [2, 4, 3, 6]);
+        assert_eq!(troop.run(19).unwrap(), [99, 97, 8, 103]);
+        troop.run(10000 - 20).unwrap();
+        assert_eq!(troop.monkey_business(), 2713310158);
+    }
+
+    #[test]
+    fn test_unbounded() {
+        // with nothing keeping worry levels down, counts match the modular version
+        let monkeys = test_monkeys();
+        let mut exact = Troop::new(&monkeys, Unbounded);
+        let mut modular = Troop::new(&monkeys, ModLcm::new(&monkeys));
+        assert_eq!(exact.run(20).unwrap(), modular.run(20).unwrap());
+        assert_eq!(exact.run(5).unwrap(), modular.run(5).unwrap());
+        assert_eq!(exact.monkey_business(), modular.monkey_business());
+    }
+
+    #[test]
+    fn test_expr() {
+        let old = Box::new(Expr::Old);
+        let c = |x| Box::new(Expr::Const(x));
+        assert_eq!(
+            expr("old * 19"),
+            Ok(("", Expr::BinOp(Op::Mul, old.clone(), c(19))))
+        );
+        assert_eq!(
+            expr("old * old + 3"),
+            Ok((
+                "",
+                Expr::BinOp(
+                    Op::Add,
+                    Box::new(Expr::BinOp(Op::Mul, old.clone(), old.clone())),
+                    c(3)
+                )
+            ))
+        );
+        assert_eq!(
+            expr("(old + 2) * 7"),
+            Ok((
+                "",
+                Expr::BinOp(Op::Mul, Box::new(Expr::BinOp(Op::Add, old, c(2))), c(7))
+            ))
+        );
+        assert_eq!(
+            expr("old - 2 - 1"),
+            Ok((
+                "",
+                Expr::BinOp(
+                    Op::Sub,
+                    Box::new(Expr::BinOp(Op::Sub, Box::new(Expr::Old), c(2))),
+                    c(1)
+                )
+            ))
+        );
+        let eval = |s| expr(s).unwrap().1.apply(&DivideBy::new(1).unwrap(), &5);
+        assert_eq!(eval("old * old + 3"), Ok(28));
+        assert_eq!(eval("3 + old * old"), Ok(28));
+        assert_eq!(eval("(old + 2) * 7"), Ok(49));
+        assert_eq!(eval("2 * ( old + 1 ) * (old + old)"), Ok(120));
+        assert_eq!(eval("1 + 2 + 3 * 4"), Ok(15));
+        assert_eq!(eval("old * old - 3 * old"), Ok(10));
+        assert_eq!(eval("old * 7 / 2 - 1"), Ok(16));
+        assert_eq!(eval("old / (old - 4) / 2"), Ok(2));
+        assert_eq!(eval("old - 6"), Err(WorryError::Negative));
+        assert_eq!(eval("old / (old - 5)"), Err(WorryError::DivisionByZero));
+
+        let modular = |s| expr(s).unwrap().1.apply(&ModLcm(7), &5);
+        assert_eq!(modular("old * 3 + 1"), Ok(2));
+        assert_eq!(modular("old - 1"), Err(WorryError::Modular('-')));
+        assert_eq!(modular("old / 5"), Err(WorryError::Modular('/')));
+        let exact = |s| expr(s).unwrap().1.apply(&Unbounded, &5.into());
+        assert_eq!(exact("old * 7 / 2 - 1"), Ok(16.into()));
+        assert_eq!(exact("old - 6"), Err(WorryError::Negative));
+        assert_eq!(exact("old / 0"), Err(WorryError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_expression_monkeys() {
+        let input = std::fs::read_to_string("test_inputs/day11.txt")
+            .unwrap()
+            .replace("new = old * 19", "new = (old + 2) * 19 + old")
+            .replace("new = old * old", "new = old * old * 3 + 1");
+        let (remaining, monkeys) = monkeys(&input).unwrap();
+        assert_eq!(remaining.trim(), "");
+        // worry levels blow up, but the modular version must still agree with the exact one
+        let mut exact = Troop::new(&monkeys, Unbounded);
+        let mut modular = Troop::new(&monkeys, ModLcm::new(&monkeys));
+        assert_eq!(exact.run(15).unwrap(), modular.run(15).unwrap());
+
+        // subtracting is fine as long as worry levels are exact
+        let subtracting = input.replace("new = old + 6", "new = old * 2 - 1");
+        let monkeys = super::monkeys(&subtracting).unwrap().1;
+        assert!(Troop::new(&monkeys, Unbounded).run(3).is_ok());
+        let mut troop = Troop::new(&monkeys, ModLcm::new(&monkeys));
+        assert_eq!(troop.run(1), Err(WorryError::Modular('-')));
+
+        // dividing is fine as long as worry levels are exact
+        let input = input.replace("new = old + 6", "new = old * 10 / 3");
+        let monkeys = super::monkeys(&input).unwrap().1;
+        assert!(Troop::new(&monkeys, DivideBy::new(3).unwrap())
+            .run(3)
+            .is_ok());
+        assert_eq!(
+            Troop::new(&monkeys, DivideBy::new(3).unwrap()).run(20),
+            Err(WorryError::Overflow)
+        );
+        assert!(Troop::new(&monkeys, Unbounded).run(3).is_ok());
+        let mut troop = Troop::new(&monkeys, ModLcm::new(&monkeys));
+        assert_eq!(troop.run(1), Err(WorryError::Modular('/')));
+        assert_eq!(extrapolate(&monkeys, 1000), Err(WorryError::Modular('/')));
+        let traces = trace(&monkeys, &ModLcm::new(&monkeys), 1).collect::<Vec<_>>();
+        assert!(traces[0].is_ok());
+        // monkey 1's own items go wrong straight away
+        assert_eq!(traces[2], Err(WorryError::Modular('/')));
+    }
+
+    #[test]
+    fn test_trace() {
+        let monkeys = test_monkeys();
+        let traces = trace(&monkeys, &DivideBy::new(3).unwrap(), 2)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(traces.len(), 10);
+        let path = |trace: &[Inspection<usize>]| {
+            trace
+                .iter()
+                .map(|i| (i.round, i.monkey, i.worry))
+                .collect::<Vec<_>>()
+        };
+        // 79 goes 0 -> 3 in round 1, then 3 -> 1 -> 2 -> 3 in round 2
+        assert_eq!(
+            path(&traces[0]),
+            [
+                (1, 0, 79),
+                (1, 3, 500),
+                (2, 1, 167),
+                (2, 2, 57),
+                (2, 3, 1083)
+            ]
+        );
+        // after one round, counts and held items match the troop's
+        let mut troop = Troop::new(&monkeys, DivideBy::new(3).unwrap());
+        troop.run(1).unwrap();
+        let mut inspections = vec![0; monkeys.len()];
+        for inspection in trace(&monkeys, &DivideBy::new(3).unwrap(), 1).flat_map(Result::unwrap) {
+            inspections[inspection.monkey] += 1;
+        }
+        assert_eq!(troop.inspections, inspections);
+
+        let exact = trace(&monkeys, &Unbounded, 1).next().unwrap().unwrap();
+        assert_eq!(exact[1].worry.to_string(), "1501");
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        let monkeys = test_monkeys();
+        let mut troop = Troop::new(&monkeys, ModLcm::new(&monkeys));
+        let mut played = 0;
+        for rounds in [1, 20, 1000, 10000] {
+            let expected = troop.run(rounds - played).unwrap().to_vec();
+            played = rounds;
+            assert_eq!(
+                extrapolate(&monkeys, rounds),
+                Ok(expected),
+                "{rounds} rounds"
+            );
+        }
+        let cycle = ItemCycle::find(&monkeys, &ModLcm::new(&monkeys), 0, 79).unwrap();
+        assert!(cycle.period > 0);
+        assert!(cycle.start < cycle.inspections.len());
+    }
+
+    #[test]
+    fn test_big_uint() {
+        let a = BigUint::from(u64::MAX as usize);
+        let square = a.mul(&a);
+        // (2^64 - 1)^2 = 2^128 - 2^65 + 1
+        assert_eq!(square.0, vec![1, 0, u32::MAX - 1, u32::MAX]);
+        assert_eq!(square.add(&BigUint::from(1)).rem(1 << 20), 2);
+        assert_eq!(
+            square.rem(23),
+            ((u64::MAX % 23) * (u64::MAX % 23) % 23) as usize
+        );
+        assert_eq!(BigUint::from(0).mul(&a), BigUint::from(0));
+        assert_eq!(BigUint::from(0).to_string(), "0");
+        assert_eq!(square.div(&a), a);
+        assert_eq!(square.div(&square.add(&a)), BigUint::from(0));
+        assert_eq!(
+            square.div(&BigUint::from(1 << 40)).0,
+            // 2^88 - 2^25
+            vec![u32::MAX - (1 << 25) + 1, u32::MAX, u32::MAX >> 8]
+        );
+        assert_eq!(square.checked_sub(&square), Some(BigUint::from(0)));
+        assert_eq!(a.checked_sub(&square), None);
+        assert_eq!(
+            square.checked_sub(&BigUint::from(2)).unwrap().to_string(),
+            "340282366920938463426481119284349108223"
+        );
+        assert!(a < square && square > a.add(&a));
+        assert_eq!(
+            square.to_string(),
+            "340282366920938463426481119284349108225"
+        );
+        assert_eq!(ModLcm::new(&test_monkeys()).0, 23 * 19 * 13 * 17);
+    }
+}
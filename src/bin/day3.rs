@@ -1,66 +1,377 @@
-use std::{collections::HashSet, fs};
+use std::{fmt::Display, fs};
 
+use anyhow::Result;
 use itertools::Itertools;
+use thiserror::Error;
 
-pub fn main() {
-    let input = fs::read_to_string("inputs/day3.txt").unwrap();
-    let data = parse1(&input);
-    println!("3.1: {}", part1(&data));
+#[derive(Debug, Error, PartialEq, Eq)]
+enum RucksackError {
+    #[error("'{0}' is not a valid item")]
+    InvalidItem(char),
+    #[error("{len} items cannot be split evenly into {compartments} compartments")]
+    UnevenCompartments { len: usize, compartments: usize },
+    #[error("group {0} has no badge")]
+    NoBadge(usize),
+    #[error("group {group} has several badges: {badges:?}")]
+    SeveralBadges { group: usize, badges: Vec<char> },
+}
 
-    let data = parse2(&input);
-    println!("3.2: {}", part2(&data).unwrap());
+fn priority(item: char) -> Result<u32, RucksackError> {
+    match item {
+        'a'..='z' => Ok(item as u32 - 'a' as u32 + 1),
+        'A'..='Z' => Ok(item as u32 - 'A' as u32 + 27),
+        _ => Err(RucksackError::InvalidItem(item)),
+    }
 }
 
-fn parse1(input: &str) -> Vec<(&str, &str)> {
-    input
-        .lines()
-        .map(|line| line.split_at(line.len() / 2))
-        .collect()
+fn item(priority: u32) -> char {
+    match priority {
+        1..=26 => (b'a' + (priority - 1) as u8) as char,
+        27..=52 => (b'A' + (priority - 27) as u8) as char,
+        _ => unreachable!("priority out of range"),
+    }
 }
-fn parse2(input: &str) -> Vec<Vec<&str>> {
-    input
-        .lines()
-        .into_iter()
-        .chunks(3)
-        .into_iter()
-        .map(Itertools::collect_vec)
-        .collect_vec()
+
+/// A set of item types, stored as a mask with bit `p` set for an item of priority `p`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Items(u64);
+
+impl Items {
+    const ALL: Items = Items(((1 << 52) - 1) << 1);
+
+    fn parse(items: &str) -> Result<Self, RucksackError> {
+        items
+            .chars()
+            .try_fold(Items(0), |acc, c| Ok(Items(acc.0 | 1 << priority(c)?)))
+    }
+
+    fn intersection(self, other: Self) -> Self {
+        Items(self.0 & other.0)
+    }
+
+    fn union(self, other: Self) -> Self {
+        Items(self.0 | other.0)
+    }
+
+    fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    fn priorities(self) -> impl Iterator<Item = u32> {
+        (1..=52).filter(move |p| self.0 & (1 << p) != 0)
+    }
+
+    fn items(self) -> impl Iterator<Item = char> {
+        self.priorities().map(item)
+    }
 }
 
-fn get_value(c: char) -> usize {
-    match (c.to_ascii_uppercase(), c.is_uppercase()) {
-        (x, true) => x as usize - 64 + 26,
-        (x, false) => x as usize - 64,
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rucksack {
+    compartments: Vec<Items>,
+}
+
+impl Rucksack {
+    fn parse(line: &str, compartments: usize) -> Result<Self, RucksackError> {
+        let len = line.chars().count();
+        if compartments == 0 || !len.is_multiple_of(compartments) {
+            return Err(RucksackError::UnevenCompartments { len, compartments });
+        }
+        let chars: Vec<char> = line.chars().collect();
+        Ok(Self {
+            compartments: chars
+                .chunks(len / compartments)
+                .map(|chunk| Items::parse(&chunk.iter().collect::<String>()))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Every item type that appears in all of the rucksack's compartments
+    fn common_items(&self) -> Items {
+        self.compartments
+            .iter()
+            .copied()
+            .reduce(Items::intersection)
+            .unwrap_or_default()
+    }
+
+    fn contents(&self) -> Items {
+        self.compartments
+            .iter()
+            .copied()
+            .fold(Items::default(), Items::union)
     }
 }
 
-fn part1(input: &[(&str, &str)]) -> usize {
-    input
+/// Every item type carried by all of the rucksacks in a group
+fn common_to_group(group: &[Rucksack]) -> Items {
+    group
         .iter()
-        .map(|(a, b)| match a.chars().find(|a_i| b.contains(*a_i)) {
-            Some(x) => get_value(x),
-            None => 0,
-        })
-        .sum()
+        .map(Rucksack::contents)
+        .fold(Items::ALL, Items::intersection)
+}
+
+/// The single item type carried by every elf in the group
+fn badge(index: usize, group: &[Rucksack]) -> Result<char, RucksackError> {
+    let common = common_to_group(group);
+    match common.len() {
+        0 => Err(RucksackError::NoBadge(index)),
+        1 => Ok(common.items().next().unwrap()),
+        _ => Err(RucksackError::SeveralBadges {
+            group: index,
+            badges: common.items().collect(),
+        }),
+    }
 }
 
-fn part2(input: &[Vec<&str>]) -> Option<usize> {
+fn parse(input: &str, compartments: usize) -> Result<Vec<Rucksack>, RucksackError> {
     input
+        .lines()
+        .map(|line| Rucksack::parse(line, compartments))
+        .collect()
+}
+
+fn part1(rucksacks: &[Rucksack]) -> u32 {
+    rucksacks
         .iter()
-        .map(|backpacks| -> Option<usize> {
-            Some(get_value(
-                backpacks
-                    .iter()
-                    .map(|backpack| HashSet::<char>::from_iter(backpack.chars()))
-                    .reduce(|acc, val| acc.intersection(&val).copied().collect())?
-                    .into_iter()
-                    .next()?,
-            ))
-        })
-        .filter(Option::is_some)
+        .flat_map(|rucksack| rucksack.common_items().priorities())
         .sum()
 }
 
+fn part2(rucksacks: &[Rucksack], group_size: usize) -> Result<u32, RucksackError> {
+    rucksacks
+        .chunks(group_size)
+        .enumerate()
+        .map(|(i, group)| priority(badge(i, group)?))
+        .sum()
+}
+
+/// Moves `count` copies of `item` from one compartment of a rucksack to the other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Move {
+    item: char,
+    count: usize,
+    from: usize,
+    to: usize,
+}
+
+impl Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "move {} '{}' from compartment {} to {}",
+            self.count, self.item, self.from, self.to
+        )
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Problem {
+    OddLength {
+        rucksack: usize,
+        len: usize,
+    },
+    InvalidItem {
+        rucksack: usize,
+        column: usize,
+        item: char,
+    },
+    NoSharedItem {
+        rucksack: usize,
+    },
+    Misplaced {
+        rucksack: usize,
+        shared: Vec<char>,
+        repair: Option<Vec<Move>>,
+    },
+    IncompleteGroup {
+        group: usize,
+        size: usize,
+    },
+    NoUniqueBadge {
+        group: usize,
+        badges: Vec<char>,
+    },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::OddLength { rucksack, len } => {
+                write!(
+                    f,
+                    "rucksack {rucksack}: {len} items cannot be split in half"
+                )
+            }
+            Problem::InvalidItem {
+                rucksack,
+                column,
+                item,
+            } => write!(f, "rucksack {rucksack}: invalid item '{item}' at {column}"),
+            Problem::NoSharedItem { rucksack } => {
+                write!(f, "rucksack {rucksack}: no item is in both compartments")
+            }
+            Problem::Misplaced {
+                rucksack,
+                shared,
+                repair: Some(moves),
+            } => write!(
+                f,
+                "rucksack {rucksack}: {shared:?} are all in both compartments, fix: {}",
+                moves.iter().map(Move::to_string).join(", ")
+            ),
+            Problem::Misplaced {
+                rucksack, shared, ..
+            } => write!(
+                f,
+                "rucksack {rucksack}: {shared:?} are all in both compartments, no fix found"
+            ),
+            Problem::IncompleteGroup { group, size } => {
+                write!(f, "group {group}: only {size} rucksacks")
+            }
+            Problem::NoUniqueBadge { group, badges } => {
+                write!(f, "group {group}: expected one badge, found {badges:?}")
+            }
+        }
+    }
+}
+
+/// How many of each item priority are in a compartment, skipping invalid items
+fn item_counts(compartment: &str) -> [usize; 53] {
+    let mut counts = [0; 53];
+    for p in compartment.chars().filter_map(|c| priority(c).ok()) {
+        counts[p as usize] += 1;
+    }
+    counts
+}
+
+/// Keeps `kept` in both compartments and moves every other item type wholly into one
+/// compartment or the other. Which way each type goes is chosen together, by how many
+/// items it sends across, so that the compartments end up the same size again
+fn repair_keeping(kept: u32, counts: &[[usize; 53]; 2]) -> Option<Vec<Move>> {
+    let total = counts.iter().flatten().sum::<usize>() as isize;
+    let cost = |moves: &[Move]| (moves.iter().map(|m| m.count).sum::<usize>(), moves.len());
+    // cheapest[total + n] is the cheapest set of moves sending n more items from
+    // compartment 0 to compartment 1 than the other way
+    let mut cheapest: Vec<Option<Vec<Move>>> = vec![None; 2 * total as usize + 1];
+    cheapest[total as usize] = Some(vec![]);
+    for (p, (&left, &right)) in counts[0].iter().zip(&counts[1]).enumerate().skip(1) {
+        let (left, right) = (left as isize, right as isize);
+        // how many copies go from compartment 0 to 1, negative for the other way
+        let sent = match (left, right) {
+            (0, 0) => continue,
+            // the kept item can be evened out too, as long as some stays on each side
+            _ if p as u32 == kept => (1 - right..left).collect_vec(),
+            _ => vec![left, -right],
+        };
+        let mut next = vec![None; cheapest.len()];
+        for (n, moves) in cheapest.iter().enumerate() {
+            let Some(moves) = moves else { continue };
+            for &sent in &sent {
+                let mut moves = moves.clone();
+                if sent != 0 {
+                    let from = if sent > 0 { 0 } else { 1 };
+                    moves.push(Move {
+                        item: item(p as u32),
+                        count: sent.unsigned_abs(),
+                        from,
+                        to: 1 - from,
+                    });
+                }
+                let at = (n as isize + sent) as usize;
+                if next[at]
+                    .as_ref()
+                    .is_none_or(|best: &Vec<Move>| cost(best) > cost(&moves))
+                {
+                    next[at] = Some(moves);
+                }
+            }
+        }
+        cheapest = next;
+    }
+    cheapest.swap_remove(total as usize)
+}
+
+/// The fewest item moves that leave exactly one item type in both compartments
+fn repair(compartments: [&str; 2]) -> Option<Vec<Move>> {
+    let counts = compartments.map(item_counts);
+    (1..=52)
+        .filter(|&p| counts[0][p as usize] > 0 && counts[1][p as usize] > 0)
+        .filter_map(|kept| repair_keeping(kept, &counts))
+        .min_by_key(|moves| moves.iter().map(|m| m.count).sum::<usize>())
+}
+
+fn validate(input: &str, group_size: usize) -> Vec<Problem> {
+    let mut problems = vec![];
+    let lines = input.lines().collect_vec();
+
+    for (rucksack, line) in lines.iter().enumerate() {
+        for (column, item) in line.chars().enumerate() {
+            if priority(item).is_err() {
+                problems.push(Problem::InvalidItem {
+                    rucksack,
+                    column,
+                    item,
+                });
+            }
+        }
+        let len = line.chars().count();
+        if len % 2 == 1 {
+            problems.push(Problem::OddLength { rucksack, len });
+            continue;
+        }
+        let halves = line.split_at(line.char_indices().nth(len / 2).map_or(0, |(i, _)| i));
+        let shared = Items::parse(&valid_items(halves.0))
+            .unwrap()
+            .intersection(Items::parse(&valid_items(halves.1)).unwrap());
+        match shared.len() {
+            0 => problems.push(Problem::NoSharedItem { rucksack }),
+            1 => {}
+            _ => problems.push(Problem::Misplaced {
+                rucksack,
+                shared: shared.items().collect(),
+                repair: repair([halves.0, halves.1]),
+            }),
+        }
+    }
+
+    for (group, rucksacks) in lines.chunks(group_size).enumerate() {
+        if rucksacks.len() < group_size {
+            problems.push(Problem::IncompleteGroup {
+                group,
+                size: rucksacks.len(),
+            });
+            continue;
+        }
+        let badges = rucksacks
+            .iter()
+            .map(|line| Items::parse(&valid_items(line)).unwrap())
+            .fold(Items::ALL, Items::intersection);
+        if badges.len() != 1 {
+            problems.push(Problem::NoUniqueBadge {
+                group,
+                badges: badges.items().collect(),
+            });
+        }
+    }
+    problems
+}
+
+fn valid_items(items: &str) -> String {
+    items.chars().filter(|&c| priority(c).is_ok()).collect()
+}
+
+pub fn main() -> Result<()> {
+    let input = fs::read_to_string("inputs/day3.txt")?;
+    for problem in validate(&input, 3) {
+        eprintln!("{problem}");
+    }
+    let rucksacks = parse(&input, 2)?;
+    println!("3.1: {}", part1(&rucksacks));
+    println!("3.2: {}", part2(&rucksacks, 3)?);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -69,7 +380,7 @@ mod tests {
     #[test]
     fn test_part1() {
         let test_input = fs::read_to_string("test_inputs/day3.txt").unwrap();
-        let data = parse1(&test_input);
+        let data = parse(&test_input, 2).unwrap();
         eprintln!("{:?}", data);
         assert_eq!(part1(&data), 157);
     }
@@ -77,8 +388,142 @@ mod tests {
     #[test]
     fn test_part2() {
         let test_input = fs::read_to_string("test_inputs/day3.txt").unwrap();
-        let data = parse2(&test_input);
+        let data = parse(&test_input, 2).unwrap();
         eprintln!("{:?}", data);
-        assert_eq!(part2(&data), Some(70));
+        assert_eq!(part2(&data, 3), Ok(70));
+    }
+
+    #[test]
+    fn test_items() {
+        let items = Items::parse("aAzZa").unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items.items().collect::<String>(), "azAZ");
+        assert_eq!(items.priorities().collect::<Vec<_>>(), vec![1, 26, 27, 52]);
+        assert_eq!(Items::parse("ab1"), Err(RucksackError::InvalidItem('1')));
+    }
+
+    #[test]
+    fn test_compartments_and_groups() {
+        let rucksack = Rucksack::parse("abcabdaeb", 3).unwrap();
+        assert_eq!(rucksack.common_items().items().collect::<String>(), "ab");
+        assert_eq!(
+            Rucksack::parse("abcd", 3),
+            Err(RucksackError::UnevenCompartments {
+                len: 4,
+                compartments: 3
+            })
+        );
+
+        let group = parse("abXY\ncdXY", 2).unwrap();
+        assert_eq!(
+            badge(0, &group),
+            Err(RucksackError::SeveralBadges {
+                group: 0,
+                badges: vec!['X', 'Y']
+            })
+        );
+        let group = parse("abcd\nefgh", 2).unwrap();
+        assert_eq!(badge(4, &group), Err(RucksackError::NoBadge(4)));
+        let test_input = fs::read_to_string("test_inputs/day3.txt").unwrap();
+        let data = parse(&test_input, 2).unwrap();
+        assert_eq!(part2(&data, 6), Err(RucksackError::NoBadge(0)));
+    }
+
+    #[test]
+    fn test_validate() {
+        let test_input = fs::read_to_string("test_inputs/day3.txt").unwrap();
+        assert_eq!(validate(&test_input, 3), vec![]);
+
+        let problems = validate(
+            "abcab
+ab1cdb
+abcd",
+            2,
+        );
+        assert_eq!(
+            problems,
+            vec![
+                Problem::OddLength {
+                    rucksack: 0,
+                    len: 5
+                },
+                Problem::InvalidItem {
+                    rucksack: 1,
+                    column: 2,
+                    item: '1'
+                },
+                Problem::NoSharedItem { rucksack: 2 },
+                Problem::NoUniqueBadge {
+                    group: 0,
+                    badges: vec!['a', 'b', 'c']
+                },
+                Problem::IncompleteGroup { group: 1, size: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repair() {
+        // 'a' is kept, the 'b' in the second half swaps with the 'c'
+        assert_eq!(
+            repair(["aabc", "abdd"]),
+            Some(vec![
+                Move {
+                    item: 'b',
+                    count: 1,
+                    from: 1,
+                    to: 0
+                },
+                Move {
+                    item: 'c',
+                    count: 1,
+                    from: 0,
+                    to: 1
+                }
+            ])
+        );
+        // keeping 'a', the misplaced 'b' and 'c' swap places with each other
+        assert_eq!(
+            repair(["abcc", "abcd"]),
+            Some(vec![
+                Move {
+                    item: 'b',
+                    count: 1,
+                    from: 0,
+                    to: 1
+                },
+                Move {
+                    item: 'c',
+                    count: 1,
+                    from: 1,
+                    to: 0
+                },
+            ])
+        );
+        // 'y' and 'z' can go either way, as long as they go opposite ways
+        let moves = repair(["ayzb", "ayzc"]).unwrap();
+        assert_eq!(moves.len(), 2);
+        assert_eq!((moves[0].item, moves[1].item), ('y', 'z'));
+        assert!(moves.iter().all(|m| m.count == 1));
+        assert_ne!(moves[0].from, moves[1].from);
+        // only moving some of the kept item across evens things out
+        assert_eq!(
+            repair(["aaab", "abcc"]),
+            Some(vec![
+                Move {
+                    item: 'a',
+                    count: 1,
+                    from: 0,
+                    to: 1
+                },
+                Move {
+                    item: 'b',
+                    count: 1,
+                    from: 1,
+                    to: 0
+                },
+            ])
+        );
+        assert_eq!(repair(["ab", "cd"]), None);
     }
 }
@@ -1,43 +1,288 @@
-use std::fs;
+use std::{collections::HashMap, fs};
 
+use itertools::Itertools;
 use nom::{
-    bytes::complete::tag, character::complete::line_ending, combinator::map_res,
-    multi::separated_list0, sequence::separated_pair, IResult,
+    bytes::complete::tag,
+    character::complete::line_ending,
+    combinator::{map, map_res, verify},
+    multi::separated_list0,
+    sequence::separated_pair,
+    IResult,
 };
 
+/// An inclusive range of sections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Interval {
+    start: usize,
+    end: usize,
+}
+
+/// How one interval sits relative to another, from the point of view of the first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Relation {
+    Equal,
+    Contains,
+    ContainedBy,
+    /// starts before the other and ends inside it
+    OverlapsLeft,
+    /// starts inside the other and ends after it
+    OverlapsRight,
+    /// doesn't overlap, but no sections lie between the two
+    Adjacent,
+    Disjoint,
+}
+
+impl Relation {
+    const ALL: [Relation; 7] = [
+        Relation::Equal,
+        Relation::Contains,
+        Relation::ContainedBy,
+        Relation::OverlapsLeft,
+        Relation::OverlapsRight,
+        Relation::Adjacent,
+        Relation::Disjoint,
+    ];
+}
+
+impl Interval {
+    fn new(start: usize, end: usize) -> Self {
+        assert!(start <= end, "interval {start}-{end} is backwards");
+        Self { start, end }
+    }
+
+    fn len(&self) -> usize {
+        self.end - self.start + 1
+    }
+
+    fn overlaps(&self, other: &Self) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn relation(&self, other: &Self) -> Relation {
+        use Relation::*;
+        if self == other {
+            Equal
+        } else if self.start <= other.start && self.end >= other.end {
+            Contains
+        } else if other.start <= self.start && other.end >= self.end {
+            ContainedBy
+        } else if self.overlaps(other) {
+            if self.start < other.start {
+                OverlapsLeft
+            } else {
+                OverlapsRight
+            }
+        } else if self.end + 1 == other.start || other.end + 1 == self.start {
+            Adjacent
+        } else {
+            Disjoint
+        }
+    }
+
+    fn intersection(&self, other: &Self) -> Option<Self> {
+        self.overlaps(other)
+            .then(|| Self::new(self.start.max(other.start), self.end.min(other.end)))
+    }
+
+    /// The smallest interval covering both, if it covers no extra sections
+    fn union(&self, other: &Self) -> Option<Self> {
+        (self.relation(other) != Relation::Disjoint)
+            .then(|| Self::new(self.start.min(other.start), self.end.max(other.end)))
+    }
+}
+
+/// A set of sections, stored as sorted intervals that are neither overlapping nor adjacent
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct IntervalSet {
+    intervals: Vec<Interval>,
+}
+
+impl IntervalSet {
+    fn insert(&mut self, interval: Interval) {
+        // everything from `first` to `last` touches the new interval and gets merged into it
+        let first = self
+            .intervals
+            .partition_point(|i| i.end + 1 < interval.start);
+        let last = self
+            .intervals
+            .partition_point(|i| i.start <= interval.end + 1);
+        let merged = self.intervals[first..last]
+            .iter()
+            .fold(interval, |acc, i| acc.union(i).unwrap());
+        self.intervals.splice(first..last, [merged]);
+    }
+
+    fn len(&self) -> usize {
+        self.intervals.iter().map(Interval::len).sum()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Interval> {
+        self.intervals.iter()
+    }
+}
+
+impl FromIterator<Interval> for IntervalSet {
+    fn from_iter<T: IntoIterator<Item = Interval>>(iter: T) -> Self {
+        let mut set = Self::default();
+        for interval in iter {
+            set.insert(interval);
+        }
+        set
+    }
+}
+
+/// Splits the sections from 1 up to the last assigned section into runs covered by the
+/// same number of elves
+fn coverage_counts(elves: &[Interval]) -> Vec<(Interval, usize)> {
+    let events = elves
+        .iter()
+        .flat_map(|elf| [(elf.start, 1), (elf.end + 1, -1)])
+        .into_grouping_map()
+        .sum();
+    let mut runs = vec![];
+    let (mut start, mut count) = (1, 0);
+    for (&section, &delta) in events.iter().sorted() {
+        if section > start {
+            runs.push((Interval::new(start, section - 1), count as usize));
+        }
+        (start, count) = (section, count + delta);
+    }
+    runs
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct CampCoverage {
+    uncovered: Vec<Interval>,
+    /// the most elves covering any one section, and which sections they cover
+    busiest: (usize, Vec<Interval>),
+    /// a smallest set of elves still covering every covered section
+    minimal_cover: Vec<usize>,
+    /// everyone else: all of them can go without leaving a section uncovered
+    redundant: Vec<usize>,
+}
+
+fn camp_coverage(elves: &[Interval]) -> CampCoverage {
+    let runs = coverage_counts(elves);
+    let uncovered = runs
+        .iter()
+        .filter(|(_, count)| *count == 0)
+        .map(|(run, _)| *run)
+        .collect();
+    let most = runs.iter().map(|(_, count)| *count).max().unwrap_or(0);
+    let busiest = runs
+        .iter()
+        .filter(|(_, count)| *count == most)
+        .map(|(run, _)| *run)
+        .collect();
+
+    // greedily take whichever elf reaches furthest from the first uncovered section
+    let by_start = (0..elves.len())
+        .sorted_by_key(|&i| elves[i].start)
+        .collect_vec();
+    let mut minimal_cover = vec![];
+    let mut next = 0;
+    for component in elves.iter().copied().collect::<IntervalSet>().iter() {
+        let mut section = component.start;
+        while section <= component.end {
+            let mut best = None;
+            while let Some(&i) = by_start.get(next).filter(|&&i| elves[i].start <= section) {
+                if best.is_none_or(|b: usize| elves[i].end > elves[b].end) {
+                    best = Some(i);
+                }
+                next += 1;
+            }
+            let best = best.expect("every section in a component is covered");
+            minimal_cover.push(best);
+            section = elves[best].end + 1;
+        }
+    }
+    minimal_cover.sort();
+    let redundant = (0..elves.len())
+        .filter(|i| minimal_cover.binary_search(i).is_err())
+        .collect();
+
+    CampCoverage {
+        uncovered,
+        busiest: (most, busiest),
+        minimal_cover,
+        redundant,
+    }
+}
+
 fn digit1(input: &str) -> IResult<&str, usize> {
     map_res(nom::character::complete::digit1, str::parse)(input)
 }
 
-fn elf_range(input: &str) -> IResult<&str, (usize, usize)> {
-    separated_pair(digit1, tag("-"), digit1)(input)
+fn elf_range(input: &str) -> IResult<&str, Interval> {
+    map(
+        verify(separated_pair(digit1, tag("-"), digit1), |(a, b)| a <= b),
+        |(start, end)| Interval::new(start, end),
+    )(input)
 }
-fn parse_line(input: &str) -> IResult<&str, ((usize, usize), (usize, usize))> {
+fn parse_line(input: &str) -> IResult<&str, (Interval, Interval)> {
     separated_pair(elf_range, tag(","), elf_range)(input)
 }
-fn parse(input: &str) -> IResult<&str, Vec<((usize, usize), (usize, usize))>> {
+fn parse(input: &str) -> IResult<&str, Vec<(Interval, Interval)>> {
     separated_list0(line_ending, parse_line)(input)
 }
 
 pub fn main() {
     let input = fs::read_to_string("inputs/day4.txt").unwrap();
     let (remaining, data) = parse(&input).unwrap();
-    assert!(remaining.is_empty());
+    assert!(remaining.trim().is_empty());
     println!("4.1: {:?}", part1(&data));
     println!("4.2: {:?}", part2(&data));
+
+    let counts = relation_counts(&data);
+    for relation in Relation::ALL {
+        println!(
+            "{:?}: {}",
+            relation,
+            counts.get(&relation).copied().unwrap_or(0)
+        );
+    }
+    println!(
+        "sections covered by each pair: {}",
+        data.iter()
+            .map(|&(a, b)| IntervalSet::from_iter([a, b]).len())
+            .sum::<usize>()
+    );
+
+    let elves = data.iter().flat_map(|&(a, b)| [a, b]).collect_vec();
+    let camp = camp_coverage(&elves);
+    println!("uncovered sections: {:?}", camp.uncovered);
+    println!(
+        "busiest sections, covered by {} elves: {:?}",
+        camp.busiest.0, camp.busiest.1
+    );
+    println!(
+        "{} of {} elves cover every covered section",
+        camp.minimal_cover.len(),
+        elves.len()
+    );
+    println!("the other {} elves are redundant", camp.redundant.len());
+}
+
+fn relation_counts(input: &[(Interval, Interval)]) -> HashMap<Relation, usize> {
+    input.iter().map(|(a, b)| a.relation(b)).counts()
 }
 
-fn part1(input: &[((usize, usize), (usize, usize))]) -> usize {
+fn part1(input: &[(Interval, Interval)]) -> usize {
     input
         .iter()
-        .filter(|((a, b), (c, d))| (a <= c && b >= d) || (c <= a && d >= b))
+        .filter(|(a, b)| {
+            matches!(
+                a.relation(b),
+                Relation::Equal | Relation::Contains | Relation::ContainedBy
+            )
+        })
         .count()
 }
 
-fn part2(input: &[((usize, usize), (usize, usize))]) -> usize {
+fn part2(input: &[(Interval, Interval)]) -> usize {
     input
         .iter()
-        .filter(|((a, b), (c, d))| c <= b && a <= d)
+        .filter(|(a, b)| a.intersection(b).is_some())
         .count()
 }
 
@@ -63,4 +308,94 @@ mod tests {
         eprintln!("{:?}", data);
         assert_eq!(part2(&data), 4);
     }
+
+    #[test]
+    fn test_relations() {
+        let test_input = fs::read_to_string("test_inputs/day4.txt").unwrap();
+        let (_, data) = parse(&test_input).unwrap();
+        let relations = data.iter().map(|(a, b)| a.relation(b)).collect_vec();
+        use Relation::*;
+        assert_eq!(
+            relations,
+            vec![
+                Disjoint,
+                Adjacent,
+                OverlapsLeft,
+                Contains,
+                ContainedBy,
+                OverlapsLeft
+            ]
+        );
+        assert_eq!(Interval::new(3, 5).relation(&Interval::new(3, 5)), Equal);
+        assert_eq!(
+            Interval::new(4, 8).relation(&Interval::new(2, 5)),
+            OverlapsRight
+        );
+    }
+
+    #[test]
+    fn test_union_intersection() {
+        let (a, b, c) = (
+            Interval::new(2, 4),
+            Interval::new(5, 7),
+            Interval::new(4, 9),
+        );
+        assert_eq!(a.union(&b), Some(Interval::new(2, 7)));
+        assert_eq!(a.intersection(&b), None);
+        assert_eq!(a.intersection(&c), Some(Interval::new(4, 4)));
+        assert_eq!(a.union(&Interval::new(6, 7)), None);
+    }
+
+    #[test]
+    fn test_interval_set() {
+        let set: IntervalSet = [(10, 12), (1, 2), (4, 5), (3, 3), (14, 20), (11, 15)]
+            .into_iter()
+            .map(|(a, b)| Interval::new(a, b))
+            .collect();
+        assert_eq!(
+            set.intervals,
+            vec![Interval::new(1, 5), Interval::new(10, 20)]
+        );
+        assert_eq!(set.len(), 16);
+    }
+
+    #[test]
+    fn test_camp_coverage() {
+        let test_input = fs::read_to_string("test_inputs/day4.txt").unwrap();
+        let (_, data) = parse(&test_input).unwrap();
+        let elves = data.iter().flat_map(|&(a, b)| [a, b]).collect_vec();
+        let camp = camp_coverage(&elves);
+        assert_eq!(camp.uncovered, vec![Interval::new(1, 1)]);
+        assert_eq!(camp.busiest, (8, vec![Interval::new(6, 6)]));
+        assert_eq!(camp.minimal_cover, vec![5, 6]);
+        assert_eq!(camp.redundant, [0, 1, 2, 3, 4, 7, 8, 9, 10, 11]);
+        let rest = camp.minimal_cover.iter().map(|&i| elves[i]).collect_vec();
+        assert_eq!(
+            rest.into_iter().collect::<IntervalSet>(),
+            elves.into_iter().collect::<IntervalSet>()
+        );
+    }
+
+    #[test]
+    fn test_coverage_counts() {
+        let elves = [
+            Interval::new(3, 5),
+            Interval::new(4, 8),
+            Interval::new(11, 11),
+        ];
+        assert_eq!(
+            coverage_counts(&elves),
+            vec![
+                (Interval::new(1, 2), 0),
+                (Interval::new(3, 3), 1),
+                (Interval::new(4, 5), 2),
+                (Interval::new(6, 8), 1),
+                (Interval::new(9, 10), 0),
+                (Interval::new(11, 11), 1),
+            ]
+        );
+        let camp = camp_coverage(&elves);
+        assert_eq!(camp.minimal_cover, vec![0, 1, 2]);
+        assert_eq!(camp.redundant, vec![]);
+    }
 }
@@ -1,5 +1,6 @@
-use std::fs;
+use std::{fmt::Display, fs};
 
+use anyhow::{Context, Result};
 use itertools::Itertools;
 use nom::{
     branch::alt,
@@ -10,8 +11,9 @@ use nom::{
     sequence::{delimited, preceded, terminated, tuple},
     IResult,
 };
+use thiserror::Error;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 struct Move {
     number: usize,
     from: usize,
@@ -72,18 +74,523 @@ fn instructions(input: &str) -> IResult<&str, Vec<Move>> {
     separated_list0(line_ending, instruction)(input)
 }
 
+impl Display for Move {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "move {} from {} to {}", self.number, self.from, self.to)
+    }
+}
+
+/// Draws the stacks (top at index 0) the way the puzzle input does, so that `stacks`
+/// parses the result back into the same stacks. There must be at least one stack
+fn render(stacks: &[Vec<char>]) -> String {
+    // always draw at least one row, so empty stacks still show up in the last row
+    let height = stacks.iter().map(Vec::len).max().unwrap_or(0).max(1);
+    let mut drawing = String::new();
+    for row in (0..height).rev() {
+        let line = stacks
+            .iter()
+            .map(|stack| match stack.len().checked_sub(row + 1) {
+                Some(i) => format!("[{}]", stack[i]),
+                None => "   ".to_owned(),
+            })
+            .join(" ");
+        drawing.push_str(&line);
+        drawing.push('\n');
+    }
+
+    // labels sit under the middle of each crate, with wider labels pushed left and at
+    // least one space between neighbours
+    let mut labels = String::new();
+    for i in 0..stacks.len() {
+        let label = (i + 1).to_string();
+        let start = if label.len() <= 2 {
+            4 * i + 1
+        } else {
+            (4 * i + 3).saturating_sub(label.len())
+        };
+        let start = start.max(labels.len() + 1);
+        labels.push_str(&" ".repeat(start - labels.len()));
+        labels.push_str(&label);
+    }
+    drawing.push_str(&labels);
+    drawing.push_str(" \n");
+    drawing
+}
+
+/// Renders a whole puzzle input, in the form `parse` reads
+fn render_puzzle(stacks: &[Vec<char>], instructions: &[Move]) -> String {
+    format!("{}\n{}\n", render(stacks), instructions.iter().join("\n"))
+}
+
 //top of stack is at index 0, which makes the parts easier
 fn parse(input: &str) -> IResult<&str, (Vec<Vec<char>>, Vec<Move>)> {
     tuple((terminated(stacks, line_ending), instructions))(input)
 }
 
-pub fn main() {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CraneModel {
+    /// moves crates one at a time
+    CrateMover9000,
+    /// moves several crates at once, keeping their order
+    CrateMover9001,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum CraneError {
+    #[error("move {index}: there is no stack {stack}")]
+    NoSuchStack { index: usize, stack: usize },
+    #[error(
+        "move {index}: cannot take {number} crates from stack {from}, it only has {available}"
+    )]
+    NotEnoughCrates {
+        index: usize,
+        number: usize,
+        from: usize,
+        available: usize,
+    },
+}
+
+/// A record of the crates lifted by one move, listed in the order they were picked up
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Lift {
+    index: usize,
+    crates: Vec<char>,
+    from: usize,
+    to: usize,
+}
+
+impl Display for Lift {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "move {}: {} from {} to {}",
+            self.index,
+            self.crates.iter().map(|c| format!("[{c}]")).join(""),
+            self.from,
+            self.to
+        )
+    }
+}
+
+/// Applies moves to the stacks one by one, unlike `part1` and `part2` which work backwards
+/// from the answer
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Crane {
+    model: CraneModel,
+    // the top of each stack is at the end, the reverse of what `parse` gives
+    stacks: Vec<Vec<char>>,
+}
+
+impl Crane {
+    fn new(model: CraneModel, stacks: &[Vec<char>]) -> Self {
+        Self {
+            model,
+            stacks: stacks
+                .iter()
+                .map(|stack| stack.iter().rev().copied().collect())
+                .collect(),
+        }
+    }
+
+    /// Applies the move numbered `index` (counting from 1), leaving the stacks untouched
+    /// if it is impossible
+    fn apply(&mut self, index: usize, instruction: &Move) -> Result<Lift, CraneError> {
+        for stack in [instruction.from, instruction.to] {
+            if stack == 0 || stack > self.stacks.len() {
+                return Err(CraneError::NoSuchStack { index, stack });
+            }
+        }
+        let from = &mut self.stacks[instruction.from - 1];
+        let available = from.len();
+        if instruction.number > available {
+            return Err(CraneError::NotEnoughCrates {
+                index,
+                number: instruction.number,
+                from: instruction.from,
+                available,
+            });
+        }
+
+        let mut crates = from.split_off(available - instruction.number);
+        if self.model == CraneModel::CrateMover9000 {
+            crates.reverse();
+        }
+        self.stacks[instruction.to - 1].extend(&crates);
+        if self.model == CraneModel::CrateMover9001 {
+            crates.reverse();
+        }
+        Ok(Lift {
+            index,
+            crates,
+            from: instruction.from,
+            to: instruction.to,
+        })
+    }
+
+    fn run(&mut self, instructions: &[Move]) -> Result<Vec<Lift>, CraneError> {
+        instructions
+            .iter()
+            .enumerate()
+            .map(|(i, instruction)| self.apply(i + 1, instruction))
+            .collect()
+    }
+
+    /// The crate on top of each stack, with a space for an empty stack
+    fn tops(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|stack| stack.last().copied().unwrap_or(' '))
+            .collect()
+    }
+
+    /// The stacks in the same layout `parse` gives, with the top at index 0
+    fn stacks(&self) -> Vec<Vec<char>> {
+        self.stacks
+            .iter()
+            .map(|stack| stack.iter().rev().copied().collect())
+            .collect()
+    }
+}
+
+impl CraneModel {
+    /// How many times the crane has to pick something up to make these moves
+    fn lifts(&self, instructions: &[Move]) -> usize {
+        match self {
+            CraneModel::CrateMover9000 => instructions.iter().map(|m| m.number).sum(),
+            CraneModel::CrateMover9001 => instructions.len(),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum PlanError {
+    #[error("the target has {target} stacks, but there are {actual}")]
+    StackCount { target: usize, actual: usize },
+    #[error("the stacks don't hold the crates the target needs")]
+    DifferentCrates,
+    #[error("no plan found within {0} moves")]
+    NoPlan(usize),
+    #[error("not enough stacks to move crates out of the way")]
+    NoRoom,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Plan {
+    moves: Vec<Move>,
+    lifts: usize,
+}
+
+/// Builds up a list of moves, applying each to a crane as it goes
+struct Planner {
+    crane: Crane,
+    moves: Vec<Move>,
+    limit: usize,
+}
+
+impl Planner {
+    fn new(model: CraneModel, stacks: &[Vec<char>]) -> Self {
+        let crates = stacks.iter().map(Vec::len).sum::<usize>();
+        Self {
+            crane: Crane::new(model, stacks),
+            moves: vec![],
+            limit: 10 * crates + stacks.len(),
+        }
+    }
+
+    fn len(&self, stack: usize) -> usize {
+        self.crane.stacks[stack].len()
+    }
+
+    /// Moves crates between the 0-indexed stacks
+    fn shift(&mut self, number: usize, from: usize, to: usize) -> Result<(), PlanError> {
+        if self.moves.len() == self.limit {
+            return Err(PlanError::NoPlan(self.limit));
+        }
+        let instruction = Move {
+            number,
+            from: from + 1,
+            to: to + 1,
+        };
+        self.crane
+            .apply(self.moves.len() + 1, &instruction)
+            .expect("planner only makes possible moves");
+        self.moves.push(instruction);
+        Ok(())
+    }
+
+    /// Somewhere to put crates that are in the way, other than `avoid`, preferring stacks
+    /// that won't mind
+    fn dump_site(&self, avoid: &[usize], wont_mind: impl Fn(usize) -> bool) -> Option<usize> {
+        let others = (0..self.crane.stacks.len()).filter(|s| !avoid.contains(s));
+        others
+            .clone()
+            .find(|&s| wont_mind(s))
+            .or(others.min_by_key(|&s| self.len(s)))
+    }
+
+    fn into_plan(self) -> Plan {
+        Plan {
+            lifts: self.crane.model.lifts(&self.moves),
+            moves: self.moves,
+        }
+    }
+}
+
+/// Finds moves that rearrange `start` into exactly `target`, both with the top at index 0.
+/// Stacks are rebuilt from the bottom up, taking the next crate each stack needs from
+/// whichever pile of out-of-place crates has it nearest the top
+fn plan_arrangement(
+    model: CraneModel,
+    start: &[Vec<char>],
+    target: &[Vec<char>],
+) -> Result<Plan, PlanError> {
+    if start.len() != target.len() {
+        return Err(PlanError::StackCount {
+            target: target.len(),
+            actual: start.len(),
+        });
+    }
+    let all_crates = |stacks: &[Vec<char>]| stacks.iter().flatten().copied().sorted().collect_vec();
+    if all_crates(start) != all_crates(target) {
+        return Err(PlanError::DifferentCrates);
+    }
+
+    let target = Crane::new(model, target).stacks;
+    let mut planner = Planner::new(model, start);
+    while planner.crane.stacks != target {
+        let stacks = &planner.crane.stacks;
+        // how many crates at the bottom of each stack are already in place
+        let fixed = (0..stacks.len())
+            .map(|s| {
+                stacks[s]
+                    .iter()
+                    .zip(&target[s])
+                    .take_while(|(a, b)| a == b)
+                    .count()
+            })
+            .collect_vec();
+        let junk = |s: usize| stacks[s].len() - fixed[s];
+
+        let Some(s) = (0..stacks.len()).find(|&s| junk(s) == 0 && fixed[s] < target[s].len())
+        else {
+            // every unfinished stack is buried, so clear the one with the least on top
+            let s = (0..stacks.len())
+                .filter(|&s| junk(s) > 0)
+                .min_by_key(|&s| junk(s))
+                .unwrap();
+            let u = planner
+                .dump_site(&[s], |u| junk(u) > 0)
+                .ok_or(PlanError::NoRoom)?;
+            planner.shift(junk(s), s, u)?;
+            continue;
+        };
+        let need = &target[s][fixed[s]..];
+
+        // the longest run of needed crates sitting on top of some other stack
+        let run = |t: usize| {
+            (1..=junk(t).min(need.len()))
+                .take_while(|&k| {
+                    let top = &stacks[t][stacks[t].len() - k..];
+                    match model {
+                        CraneModel::CrateMover9000 => top.iter().rev().eq(&need[..k]),
+                        CraneModel::CrateMover9001 => top == &need[..k],
+                    }
+                })
+                .last()
+        };
+        if let Some((t, k)) = (0..stacks.len())
+            .filter(|&t| t != s)
+            .filter_map(|t| run(t).map(|k| (t, k)))
+            .max_by_key(|&(_, k)| k)
+        {
+            planner.shift(k, t, s)?;
+            continue;
+        }
+
+        // otherwise dig out the needed crate that has the fewest crates on top of it
+        let (t, depth) = (0..stacks.len())
+            .filter(|&t| t != s)
+            .filter_map(|t| {
+                let i = (fixed[t]..stacks[t].len())
+                    .rev()
+                    .find(|&i| stacks[t][i] == need[0])?;
+                Some((t, stacks[t].len() - 1 - i))
+            })
+            .min_by_key(|&(_, depth)| depth)
+            .expect("a needed crate is always out of place somewhere");
+        let u = planner
+            .dump_site(&[s, t], |u| junk(u) > 0)
+            .ok_or(PlanError::NoRoom)?;
+        planner.shift(depth, t, u)?;
+    }
+    Ok(planner.into_plan())
+}
+
+/// Finds moves that leave `tops[i]` on top of stack `i`, trying both digging for each
+/// wanted crate directly and rearranging into a fixed target, and keeping the shorter plan
+fn plan_tops(model: CraneModel, start: &[Vec<char>], tops: &str) -> Result<Plan, PlanError> {
+    let tops = tops.chars().collect_vec();
+    if start.len() != tops.len() {
+        return Err(PlanError::StackCount {
+            target: tops.len(),
+            actual: start.len(),
+        });
+    }
+    let available = start.iter().flatten().counts();
+    if tops
+        .iter()
+        .counts()
+        .iter()
+        .any(|(c, n)| available.get(c).is_none_or(|a| a < n))
+    {
+        return Err(PlanError::DifferentCrates);
+    }
+
+    let rearranged = plan_arrangement(model, start, &arrangement_with_tops(start, &tops));
+    match (dig_for_tops(model, start, &tops), rearranged) {
+        (Ok(a), Ok(b)) => Ok(if (b.moves.len(), b.lifts) < (a.moves.len(), a.lifts) {
+            b
+        } else {
+            a
+        }),
+        (Ok(plan), Err(_)) | (Err(_), Ok(plan)) => Ok(plan),
+        (Err(_), Err(e)) => Err(e),
+    }
+}
+
+/// The stacks with one copy of each wanted crate pulled up to the top of its stack,
+/// choosing copies as near the top as possible
+fn arrangement_with_tops(start: &[Vec<char>], tops: &[char]) -> Vec<Vec<char>> {
+    let mut chosen = vec![None; tops.len()];
+    for s in 0..tops.len() {
+        if start[s].first() == Some(&tops[s]) {
+            chosen[s] = Some((s, 0));
+        }
+    }
+    for s in 0..tops.len() {
+        if chosen[s].is_none() {
+            chosen[s] = (0..start.len())
+                .flat_map(|t| (0..start[t].len()).map(move |i| (t, i)))
+                .filter(|&(t, i)| start[t][i] == tops[s] && !chosen.contains(&Some((t, i))))
+                .min_by_key(|&(_, i)| i);
+        }
+    }
+    (0..start.len())
+        .map(|s| {
+            std::iter::once(tops[s])
+                .chain(
+                    (0..start[s].len())
+                        .filter(|&i| !chosen.contains(&Some((s, i))))
+                        .map(|i| start[s][i]),
+                )
+                .collect()
+        })
+        .collect()
+}
+
+/// Fixes the stacks one at a time, fetching whichever wanted crate is quickest to reach
+fn dig_for_tops(model: CraneModel, start: &[Vec<char>], tops: &[char]) -> Result<Plan, PlanError> {
+    let mut planner = Planner::new(model, start);
+    loop {
+        let stacks = &planner.crane.stacks;
+        let done = |s: usize| stacks[s].last() == Some(&tops[s]);
+        // the unfinished stack whose wanted crate is quickest to reach, avoiding taking
+        // from finished stacks if we can
+        let Some((s, t, depth)) = (0..stacks.len())
+            .filter(|&s| !done(s))
+            .flat_map(|s| {
+                (0..stacks.len()).flat_map(move |t| {
+                    (0..stacks[t].len())
+                        .filter(move |&i| stacks[t][i] == tops[s])
+                        .map(move |i| (s, t, stacks[t].len() - 1 - i))
+                })
+            })
+            .min_by_key(|&(s, t, depth)| (t != s && done(t), depth))
+        else {
+            break;
+        };
+
+        if t == s {
+            let u = planner
+                .dump_site(&[s], |u| !done(u))
+                .ok_or(PlanError::NoRoom)?;
+            planner.shift(depth, s, u)?;
+        } else {
+            match model {
+                // moving one at a time turns the pile over, leaving the wanted crate on top
+                CraneModel::CrateMover9000 => planner.shift(depth + 1, t, s)?,
+                CraneModel::CrateMover9001 => {
+                    if depth > 0 {
+                        planner.shift(depth, t, s)?;
+                    }
+                    planner.shift(1, t, s)?;
+                }
+            }
+        }
+    }
+    Ok(planner.into_plan())
+}
+
+pub fn main() -> Result<()> {
     let input = fs::read_to_string("inputs/day5.txt").unwrap();
     let (remaining, (stack, instructions)) = parse(&input).unwrap();
     assert!(remaining.chars().all(char::is_whitespace));
 
+    // `--trace` draws the stacks after every move, `--after <n>` prints a puzzle input
+    // starting from the 9000's stacks after n moves, and `--plan <tops|drawing file>` finds
+    // moves that leave the given crates on top, or the stacks looking like the drawing
+    let args = std::env::args().collect_vec();
+    if let Some(i) = args.iter().position(|arg| arg == "--plan") {
+        let target = args
+            .get(i + 1)
+            .context("--plan needs the crates to put on top, or a drawing")?;
+        let drawing = fs::read_to_string(target).ok();
+        for model in [CraneModel::CrateMover9000, CraneModel::CrateMover9001] {
+            let plan = match &drawing {
+                Some(drawing) => {
+                    let (_, target) = stacks(drawing)
+                        .map_err(|e| e.to_owned())
+                        .context("bad drawing")?;
+                    plan_arrangement(model, &stack, &target)?
+                }
+                None => plan_tops(model, &stack, target)?,
+            };
+            println!(
+                "{model:?}: {} moves, {} lifts\n{}",
+                plan.moves.len(),
+                plan.lifts,
+                plan.moves.iter().join("\n")
+            );
+        }
+        return Ok(());
+    }
+    let trace = args.iter().any(|arg| arg == "--trace");
+    if let Some(n) = args.iter().position(|arg| arg == "--after") {
+        let n: usize = args
+            .get(n + 1)
+            .context("--after needs a move count")?
+            .parse()?;
+        let (done, rest) = instructions.split_at(n.min(instructions.len()));
+        let mut crane = Crane::new(CraneModel::CrateMover9000, &stack);
+        crane.run(done)?;
+        print!("{}", render_puzzle(&crane.stacks(), rest));
+        return Ok(());
+    }
+
     println!("4.1: {}", part1(&stack, &instructions));
     println!("4.2: {}", part2(&stack, &instructions));
+
+    for model in [CraneModel::CrateMover9000, CraneModel::CrateMover9001] {
+        let mut crane = Crane::new(model, &stack);
+        for (i, instruction) in instructions.iter().enumerate() {
+            let lift = crane.apply(i + 1, instruction)?;
+            if trace {
+                println!("{lift}\n{}", render(&crane.stacks()));
+            }
+        }
+        println!("{model:?}: {}\n{}", crane.tops(), render(&crane.stacks()));
+    }
+    Ok(())
 }
 
 fn part1(stack: &[Vec<char>], instructions: &[Move]) -> String {
@@ -192,4 +699,160 @@ mod tests {
 
         assert_eq!("MCD", part2(&stack, &instructions));
     }
+
+    #[test]
+    fn test_crane() {
+        let input = fs::read_to_string("test_inputs/day5.txt").unwrap();
+        let (_, (stack, instructions)) = parse(&input).unwrap();
+
+        let mut crane = Crane::new(CraneModel::CrateMover9000, &stack);
+        let lifts = crane.run(&instructions).unwrap();
+        assert_eq!(crane.tops(), part1(&stack, &instructions));
+        assert_eq!(
+            crane.stacks(),
+            vec![vec!['C'], vec!['M'], vec!['Z', 'N', 'D', 'P']]
+        );
+        assert_eq!(lifts[1].to_string(), "move 2: [D][N][Z] from 1 to 3");
+
+        let mut crane = Crane::new(CraneModel::CrateMover9001, &stack);
+        let lifts = crane.run(&instructions).unwrap();
+        assert_eq!(crane.tops(), part2(&stack, &instructions));
+        assert_eq!(lifts[1].to_string(), "move 2: [D][N][Z] from 1 to 3");
+        assert_eq!(lifts[2].to_string(), "move 3: [C][M] from 2 to 1");
+    }
+
+    #[test]
+    fn test_crane_errors() {
+        let input = fs::read_to_string("test_inputs/day5.txt").unwrap();
+        let (_, (stack, mut instructions)) = parse(&input).unwrap();
+        instructions[2].number = 3;
+
+        let mut crane = Crane::new(CraneModel::CrateMover9001, &stack);
+        assert_eq!(
+            crane.run(&instructions),
+            Err(CraneError::NotEnoughCrates {
+                index: 3,
+                number: 3,
+                from: 2,
+                available: 2
+            })
+        );
+        let mut crane = Crane::new(CraneModel::CrateMover9000, &stack);
+        assert_eq!(
+            crane.apply(
+                1,
+                &Move {
+                    number: 1,
+                    from: 4,
+                    to: 1
+                }
+            ),
+            Err(CraneError::NoSuchStack { index: 1, stack: 4 })
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let input = fs::read_to_string("test_inputs/day5.txt").unwrap();
+        let (_, (stack, instructions)) = parse(&input).unwrap();
+        assert_eq!(
+            render_puzzle(&stack, &instructions).trim_end(),
+            input.trim_end()
+        );
+
+        let mut crane = Crane::new(CraneModel::CrateMover9000, &stack);
+        crane.run(&instructions[..2]).unwrap();
+        assert_eq!(
+            render(&crane.stacks()),
+            "        [Z]\n        [N]\n    [C] [D]\n    [M] [P]\n 1   2   3 \n"
+        );
+    }
+
+    #[test]
+    fn test_render_round_trip() {
+        for n in [1, 9, 10, 12, 101] {
+            let stack = (0..n)
+                .map(|i| {
+                    (0..i % 4)
+                        .map(|j| (b'A' + (i + j) as u8 % 26) as char)
+                        .collect()
+                })
+                .collect_vec();
+            let drawing = render(&stack);
+            assert_eq!(stacks(&drawing), Ok(("", stack)), "{drawing}");
+        }
+        let labels = render(&vec![vec![]; 11]).lines().last().unwrap().to_owned();
+        assert!(labels.ends_with(" 9   10  11 "));
+    }
+
+    #[test]
+    fn test_plan_arrangement() {
+        let input = fs::read_to_string("test_inputs/day5.txt").unwrap();
+        let (_, (stack, instructions)) = parse(&input).unwrap();
+
+        for model in [CraneModel::CrateMover9000, CraneModel::CrateMover9001] {
+            let mut crane = Crane::new(model, &stack);
+            crane.run(&instructions).unwrap();
+            let target = crane.stacks();
+
+            let plan = plan_arrangement(model, &stack, &target).unwrap();
+            let mut crane = Crane::new(model, &stack);
+            crane.run(&plan.moves).unwrap();
+            assert_eq!(crane.stacks(), target);
+            assert_eq!(plan.lifts, model.lifts(&plan.moves));
+        }
+
+        let reversed = stack.iter().rev().cloned().collect_vec();
+        let plan = plan_arrangement(CraneModel::CrateMover9001, &stack, &reversed).unwrap();
+        let mut crane = Crane::new(CraneModel::CrateMover9001, &stack);
+        crane.run(&plan.moves).unwrap();
+        assert_eq!(crane.stacks(), reversed);
+
+        assert_eq!(
+            plan_arrangement(CraneModel::CrateMover9000, &stack, &stack[..2]),
+            Err(PlanError::StackCount {
+                target: 2,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            plan_arrangement(
+                CraneModel::CrateMover9000,
+                &stack,
+                &[vec!['N', 'Z'], vec!['D', 'C', 'M'], vec!['Q']]
+            ),
+            Err(PlanError::DifferentCrates)
+        );
+    }
+
+    #[test]
+    fn test_plan_tops() {
+        let input = fs::read_to_string("test_inputs/day5.txt").unwrap();
+        let (_, (stack, _)) = parse(&input).unwrap();
+
+        for model in [CraneModel::CrateMover9000, CraneModel::CrateMover9001] {
+            for tops in ["CMZ", "MCD", "ZPN", "NDP"] {
+                let plan = plan_tops(model, &stack, tops).unwrap();
+                let mut crane = Crane::new(model, &stack);
+                crane.run(&plan.moves).unwrap();
+                assert_eq!(crane.tops(), tops);
+            }
+        }
+        // the 9000 turns the top of the first stack over in one go, the 9001 needs two
+        let stack = vec![vec!['X', 'Y', 'Z', 'W'], vec!['A'], vec!['B']];
+        let plan = plan_tops(CraneModel::CrateMover9000, &stack, "WZB").unwrap();
+        assert_eq!(plan.moves.iter().join(", "), "move 3 from 1 to 2");
+        assert_eq!(plan.lifts, 3);
+        let plan = plan_tops(CraneModel::CrateMover9001, &stack, "WZB").unwrap();
+        assert_eq!(
+            plan.moves.iter().join(", "),
+            "move 2 from 1 to 2, move 1 from 1 to 2"
+        );
+        assert_eq!(plan.lifts, 2);
+
+        assert_eq!(
+            plan_tops(CraneModel::CrateMover9000, &stack, "ZZB"),
+            Err(PlanError::DifferentCrates)
+        );
+    }
 }
@@ -1,40 +1,183 @@
-use nom::error::ErrorKind::TakeWhile1;
-use nom::IResult;
-use std::fs;
-
-fn first_repeat(input: &str) -> Option<usize> {
-    for i in 0..input.len() {
-        for j in (i + 1)..input.len() {
-            if input[i..=i] == input[j..=j] {
-                return Some(i);
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::ops::Range;
+
+use anyhow::{Context, Result};
+
+/// Watches a stream one byte at a time for the last `n` bytes being all different.
+/// Keeps a count of each byte in the window and of how many bytes appear more than once
+/// in it, so each step costs the same whatever the window size
+struct MarkerDetector {
+    n: usize,
+    window: Vec<u8>,
+    counts: [usize; 256],
+    duplicates: usize,
+    position: usize,
+}
+
+impl MarkerDetector {
+    fn new(n: usize) -> Self {
+        Self {
+            n,
+            window: vec![0; n],
+            counts: [0; 256],
+            duplicates: 0,
+            position: 0,
+        }
+    }
+
+    /// Feeds in the next byte, returning whether the window now ends in a marker
+    fn push(&mut self, byte: u8) -> bool {
+        if self.n == 0 {
+            self.position += 1;
+            return true;
+        }
+        let slot = self.position % self.n;
+        if self.position >= self.n {
+            let old = self.window[slot] as usize;
+            self.counts[old] -= 1;
+            if self.counts[old] == 1 {
+                self.duplicates -= 1;
             }
         }
+        self.window[slot] = byte;
+        self.counts[byte as usize] += 1;
+        if self.counts[byte as usize] == 2 {
+            self.duplicates += 1;
+        }
+        self.position += 1;
+        self.is_marker()
+    }
+
+    fn is_marker(&self) -> bool {
+        self.position >= self.n && self.duplicates == 0
+    }
+}
+
+/// How many bytes have to be read before the last `n` are all different
+fn find_marker(source: impl Read, n: usize) -> io::Result<Option<usize>> {
+    let mut detector = MarkerDetector::new(n);
+    if detector.is_marker() {
+        return Ok(Some(0));
+    }
+    for byte in BufReader::new(source).bytes() {
+        if detector.push(byte?) {
+            return Ok(Some(detector.position));
+        }
     }
-    None
+    Ok(None)
 }
 
-fn until_n_distinct<const N: usize>(input: &str) -> IResult<&str, &str> {
-    let mut index = 0;
-    loop {
-        if input.len() < N + index {
-            return Err(nom::Err::Failure(nom::error::Error::new(input, TakeWhile1)));
+/// Every position at which the last `n` bytes are all different, including overlapping ones
+fn all_markers(stream: &[u8], n: usize) -> Vec<usize> {
+    let mut detector = MarkerDetector::new(n);
+    stream
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &byte)| detector.push(byte).then_some(i + 1))
+        .collect()
+}
+
+/// The first longest stretch of the stream with no repeated bytes
+fn longest_distinct_run(stream: &[u8]) -> Range<usize> {
+    let mut last_seen = [None; 256];
+    let (mut best, mut start) = (0..0, 0);
+    for (i, &byte) in stream.iter().enumerate() {
+        if let Some(previous) = last_seen[byte as usize] {
+            start = start.max(previous + 1);
         }
-        match first_repeat(&input[index..index + N]) {
-            Some(x) => index += x + 1,
-            None => return Ok((&input[index + N..], &input[..index + N])),
+        last_seen[byte as usize] = Some(i);
+        if i + 1 - start > best.len() {
+            best = start..i + 1;
         }
     }
+    best
+}
+
+/// A marker and the data that follows it, up to the next marker or the end of the stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Frame {
+    marker: Range<usize>,
+    payload: Range<usize>,
+}
+
+/// Splits the stream at markers of `n` different bytes. A marker only counts if it lies
+/// wholly after the previous one, so a payload is never part of its own marker
+fn frames(stream: &[u8], n: usize) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = vec![];
+    let mut detector = MarkerDetector::new(n);
+    for (i, &byte) in stream.iter().enumerate() {
+        if detector.push(byte) {
+            if let Some(last) = frames.last_mut() {
+                last.payload.end = i + 1 - n;
+            }
+            frames.push(Frame {
+                marker: i + 1 - n..i + 1,
+                payload: i + 1..stream.len(),
+            });
+            detector = MarkerDetector::new(n);
+        }
+    }
+    frames
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct SignalAnalysis {
+    packet_markers: Vec<usize>,
+    message_markers: Vec<usize>,
+    longest_run: Range<usize>,
+    packets: Vec<Frame>,
+    messages: Vec<Frame>,
 }
 
-fn index_of_n_distinct<const N: usize>(input: &str) -> IResult<&str, usize> {
-    nom::combinator::map(until_n_distinct::<N>, |x| x.len())(input)
+fn analyse(stream: &[u8]) -> SignalAnalysis {
+    SignalAnalysis {
+        packet_markers: all_markers(stream, 4),
+        message_markers: all_markers(stream, 14),
+        longest_run: longest_distinct_run(stream),
+        packets: frames(stream, 4),
+        messages: frames(stream, 14),
+    }
 }
 
-fn main() {
-    let input = fs::read_to_string("inputs/day6.txt").unwrap();
+fn main() -> Result<()> {
+    let open = || File::open("inputs/day6.txt");
+
+    println!("6.1: {}", find_marker(open()?, 4)?.context("no marker")?);
+    println!("6.2: {}", find_marker(open()?, 14)?.context("no marker")?);
+
+    let stream = fs::read("inputs/day6.txt")?;
+    let analysis = analyse(stream.trim_ascii_end());
+    println!(
+        "{} start-of-packet markers, {} start-of-message markers",
+        analysis.packet_markers.len(),
+        analysis.message_markers.len()
+    );
+    println!(
+        "longest run of different bytes: {:?} ({} bytes)",
+        analysis.longest_run,
+        analysis.longest_run.len()
+    );
+    println!(
+        "decodes into {} packets and {} messages",
+        analysis.packets.len(),
+        analysis.messages.len()
+    );
 
-    println!("4.1: {}", index_of_n_distinct::<4>(&input).unwrap().1);
-    println!("4.2: {}", index_of_n_distinct::<14>(&input).unwrap().1);
+    // any other window sizes given on the command line are searched for in stdin
+    let sizes = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut stream = vec![];
+    if !sizes.is_empty() {
+        io::stdin().read_to_end(&mut stream)?;
+    }
+    for n in sizes {
+        let n = n.parse()?;
+        match find_marker(&stream[..], n)? {
+            Some(position) => println!("{n}: {position}"),
+            None => println!("{n}: no marker"),
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -44,34 +187,41 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_first_repeat() {
-        assert_eq!(first_repeat("abbaba"), Some(0));
-        assert_eq!(first_repeat("bababa"), Some(0));
-        assert_eq!(first_repeat("cabbba"), Some(1));
+    fn test_detector() {
+        let mut detector = MarkerDetector::new(3);
+        let markers = b"abbaba".iter().map(|&b| detector.push(b)).collect_vec();
+        assert_eq!(markers, vec![false, false, false, false, false, false]);
+        let mut detector = MarkerDetector::new(3);
+        let markers = b"cabbca".iter().map(|&b| detector.push(b)).collect_vec();
+        assert_eq!(markers, vec![false, false, true, false, false, true]);
     }
 
     #[test]
     fn test_4_repeated() {
+        assert_eq!(find_marker(&b"aabbccddefg"[..], 4).unwrap(), Some(11));
+        assert_eq!(find_marker(&b"aabbccddefghij"[..], 4).unwrap(), Some(11));
         assert_eq!(
-            until_n_distinct::<4>("aabbccddefg"),
-            Ok(("", "aabbccddefg"))
+            find_marker(&b"mjqjpqmgbljsphdztnvjfqwrcgsmlb"[..], 4).unwrap(),
+            Some(7)
         );
-        assert_eq!(
-            until_n_distinct::<4>("aabbccddefghij"),
-            Ok(("hij", "aabbccddefg"))
-        );
-        assert_eq!(
-            until_n_distinct::<4>("mjqjpqmgbljsphdztnvjfqwrcgsmlb"),
-            Ok(("gbljsphdztnvjfqwrcgsmlb", "mjqjpqm"))
-        )
+        assert_eq!(find_marker(&b"aabbccdd"[..], 4).unwrap(), None);
+        assert_eq!(find_marker(&b""[..], 0).unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        // 'é' is two bytes, so both halves count as symbols
+        assert_eq!(find_marker("aéa".as_bytes(), 3).unwrap(), Some(3));
+        assert_eq!(find_marker("ééab".as_bytes(), 3).unwrap(), Some(5));
     }
+
     #[test]
     fn test_part1() {
         let test_input = fs::read_to_string("test_inputs/day6.txt").unwrap();
         let res = test_input
             .lines()
             .filter(|line| !line.is_empty())
-            .map(|x| index_of_n_distinct::<4>(x).unwrap().1)
+            .map(|x| find_marker(x.as_bytes(), 4).unwrap().unwrap())
             .collect_vec();
         assert_eq!(res, vec![7, 5, 6, 10, 11]);
     }
@@ -81,8 +231,57 @@ mod tests {
         let res = test_input
             .lines()
             .filter(|line| !line.is_empty())
-            .map(|x| index_of_n_distinct::<14>(x).unwrap().1)
+            .map(|x| find_marker(x.as_bytes(), 14).unwrap().unwrap())
             .collect_vec();
         assert_eq!(res, vec![19, 23, 23, 29, 26]);
     }
+
+    #[test]
+    fn test_analyse() {
+        let stream = b"mjqjpqmgbljsphdztnvjfqwrcgsmlb";
+        let analysis = analyse(stream);
+        assert_eq!(analysis.packet_markers[..3], [7, 8, 9]);
+        assert_eq!(analysis.message_markers[0], 19);
+        assert_eq!(analysis.longest_run, 12..30);
+        assert_eq!(
+            analysis.messages,
+            vec![Frame {
+                marker: 5..19,
+                payload: 19..30
+            }]
+        );
+        assert_eq!(
+            analysis.packets[..2],
+            [
+                Frame {
+                    marker: 3..7,
+                    payload: 7..7
+                },
+                Frame {
+                    marker: 7..11,
+                    payload: 11..11
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frames() {
+        assert_eq!(
+            frames(b"abcaaxyzbb", 3),
+            vec![
+                Frame {
+                    marker: 0..3,
+                    payload: 3..4
+                },
+                Frame {
+                    marker: 4..7,
+                    payload: 7..10
+                }
+            ]
+        );
+        assert_eq!(all_markers(b"abcaaxyzbb", 3), vec![3, 4, 7, 8, 9]);
+        assert_eq!(longest_distinct_run(b""), 0..0);
+        assert_eq!(longest_distinct_run(b"abcaaxyzbb"), 4..9);
+    }
 }
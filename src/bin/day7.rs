@@ -1,7 +1,11 @@
-use anyhow::{Context, Result};
-use std::collections::HashMap;
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Display;
 use std::fs;
+use std::io;
+use std::path::Path;
 
 use nom::{
     branch::alt,
@@ -35,8 +39,8 @@ impl Display for File {
 #[derive(Debug, PartialEq, Eq)]
 struct Directory {
     name: String,
-    files: HashMap<String, File>,
-    subdirectories: HashMap<String, Directory>,
+    files: BTreeMap<String, File>,
+    subdirectories: BTreeMap<String, Directory>,
 }
 impl Display for Directory {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -64,34 +68,256 @@ impl Directory {
     fn new(name: String) -> Self {
         Self {
             name,
-            files: HashMap::new(),
-            subdirectories: HashMap::new(),
+            files: BTreeMap::new(),
+            subdirectories: BTreeMap::new(),
         }
     }
+}
+
+/// The size of everything in a tree, worked out in a single pass
+#[derive(Debug, Default, PartialEq, Eq)]
+struct DiskUsage {
+    /// the total size of every directory, by path
+    dirs: BTreeMap<String, usize>,
+    files: BTreeMap<String, usize>,
+}
 
-    fn merge(&mut self, other: Self) {
-        self.files.extend(other.files);
-        self.subdirectories.extend(other.subdirectories);
+impl DiskUsage {
+    fn new(root: &Directory) -> Self {
+        let mut usage = Self::default();
+        usage.add(root, &mut vec![]);
+        usage
     }
 
-    fn size(&self) -> usize {
-        self.files.values().map(|f| f.size).sum::<usize>()
-            + self
-                .subdirectories
-                .values()
-                .map(|d| d.size())
-                .sum::<usize>()
+    fn add(&mut self, dir: &Directory, path: &mut Vec<String>) -> usize {
+        let mut size = 0;
+        for file in dir.files.values() {
+            self.files.insert(child_path(path, &file.name), file.size);
+            size += file.size;
+        }
+        for subdirectory in dir.subdirectories.values() {
+            path.push(subdirectory.name.clone());
+            size += self.add(subdirectory, path);
+            path.pop();
+        }
+        self.dirs.insert(path_string(path), size);
+        size
     }
 
-    fn walk_dirs(&self) -> Vec<&Directory> {
-        Iterator::chain(
-            std::iter::once(self),
-            self.subdirectories.values().flat_map(|d| d.walk_dirs()),
-        )
-        .collect()
+    fn total(&self) -> usize {
+        self.dirs["/"]
+    }
+
+    /// Every directory, biggest first
+    fn du(&self) -> Vec<(&str, usize)> {
+        self.dirs
+            .iter()
+            .map(|(path, &size)| (path.as_str(), size))
+            .sorted_by_key(|&(path, size)| (Reverse(size), path))
+            .collect()
+    }
+
+    /// Every file and directory bigger than `size`, by path
+    fn find_larger(&self, size: usize) -> Vec<(&str, usize)> {
+        self.dirs
+            .iter()
+            .chain(&self.files)
+            .filter(|(_, &s)| s > size)
+            .map(|(path, &s)| (path.as_str(), s))
+            .sorted()
+            .collect()
+    }
+
+    /// The `n` biggest files, biggest first
+    fn largest_files(&self, n: usize) -> Vec<(&str, usize)> {
+        self.files
+            .iter()
+            .map(|(path, &size)| (path.as_str(), size))
+            .sorted_by_key(|&(path, size)| (Reverse(size), path))
+            .take(n)
+            .collect()
+    }
+
+    /// Draws the tree like `tree --du`, with everything in name order
+    fn tree(&self, root: &Directory) -> String {
+        let mut lines = vec![format!("/ ({})", self.total())];
+        self.tree_lines(root, &mut vec![], "", &mut lines);
+        lines.join("\n")
+    }
+
+    fn tree_lines(
+        &self,
+        dir: &Directory,
+        path: &mut Vec<String>,
+        indent: &str,
+        lines: &mut Vec<String>,
+    ) {
+        let entries = dir
+            .files
+            .keys()
+            .map(|name| (name, false))
+            .chain(dir.subdirectories.keys().map(|name| (name, true)))
+            .sorted()
+            .collect_vec();
+        for (i, (name, is_dir)) in entries.iter().enumerate() {
+            let last = i + 1 == entries.len();
+            let (branch, next_indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let full_path = child_path(path, name);
+            if *is_dir {
+                lines.push(format!(
+                    "{indent}{branch}{name}/ ({})",
+                    self.dirs[&full_path]
+                ));
+                path.push(name.to_string());
+                self.tree_lines(
+                    &dir.subdirectories[*name],
+                    path,
+                    &format!("{indent}{next_indent}"),
+                    lines,
+                );
+                path.pop();
+            } else {
+                lines.push(format!(
+                    "{indent}{branch}{name} ({})",
+                    self.files[&full_path]
+                ));
+            }
+        }
+    }
+}
+
+/// Sizes the way `du -h` shows them, rounding up to one decimal place below 10
+fn human_size(size: usize) -> String {
+    let units = ["K", "M", "G", "T"];
+    let round = |value: f64| match (value * 10.0).ceil() < 100.0 {
+        true => (value * 10.0).ceil() / 10.0,
+        false => value.ceil(),
+    };
+    // rounding up can reach the next unit too, like 1048575 showing as 1.0M
+    let (mut value, mut scale) = (size as f64, 0);
+    while scale < units.len() && (value >= 1024.0 || (scale > 0 && round(value) >= 1024.0)) {
+        value /= 1024.0;
+        scale += 1;
+    }
+    if scale == 0 {
+        return size.to_string();
+    }
+    let unit = units[scale - 1];
+    if round(value) < 10.0 {
+        format!("{:.1}{unit}", round(value))
+    } else {
+        format!("{}{unit}", round(value))
     }
 }
 
+/// A JSON string literal for `s`
+fn json_string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// The tree as nested JSON objects, with every directory's total size
+fn to_json(dir: &Directory, usage: &DiskUsage, path: &mut Vec<String>) -> String {
+    let files = dir
+        .files
+        .values()
+        .map(|file| {
+            format!(
+                "{{\"name\":{},\"size\":{}}}",
+                json_string(&file.name),
+                file.size
+            )
+        })
+        .join(",");
+    let directories = dir
+        .subdirectories
+        .values()
+        .map(|subdirectory| {
+            path.push(subdirectory.name.clone());
+            let json = to_json(subdirectory, usage, path);
+            path.pop();
+            json
+        })
+        .join(",");
+    format!(
+        "{{\"name\":{},\"size\":{},\"files\":[{files}],\"directories\":[{directories}]}}",
+        json_string(&dir.name),
+        usage.dirs[&path_string(path)]
+    )
+}
+
+/// Every file and directory as `path,kind,size` rows, in path order
+fn to_csv(usage: &DiskUsage) -> String {
+    let field = |s: &str| match s.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_owned(),
+    };
+    let dirs = usage.dirs.iter().map(|(path, size)| (path, "dir", size));
+    let files = usage.files.iter().map(|(path, size)| (path, "file", size));
+    let mut csv = String::from("path,kind,size\n");
+    for (path, kind, size) in dirs.merge(files) {
+        csv.push_str(&format!("{},{kind},{size}\n", field(path)));
+    }
+    csv
+}
+
+/// Names that would step outside of the directory they're meant to be in
+fn is_unsafe_name(name: &str) -> bool {
+    name.is_empty() || name == "." || name == ".." || name.contains('/')
+}
+
+/// Recreates the tree under `at`, with each file a sparse file of the right size
+fn materialise(dir: &Directory, at: &Path) -> io::Result<()> {
+    fs::create_dir_all(at)?;
+    for name in dir.files.keys().chain(dir.subdirectories.keys()) {
+        if is_unsafe_name(name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("can't create {name:?} in {}", at.display()),
+            ));
+        }
+    }
+    for file in dir.files.values() {
+        fs::File::create(at.join(&file.name))?.set_len(file.size as u64)?;
+    }
+    for subdirectory in dir.subdirectories.values() {
+        materialise(subdirectory, &at.join(&subdirectory.name))?;
+    }
+    Ok(())
+}
+
+/// Reads a tree back from disk, going by the length of each file
+fn read_tree(name: String, at: &Path) -> io::Result<Directory> {
+    let mut dir = Directory::new(name);
+    for entry in fs::read_dir(at)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            let subdirectory = read_tree(name.clone(), &entry.path())?;
+            dir.subdirectories.insert(name, subdirectory);
+        } else {
+            let size = metadata.len() as usize;
+            dir.files.insert(name.clone(), File { size, name });
+        }
+    }
+    Ok(dir)
+}
+
 fn parse_instruction(input: &str) -> IResult<&str, ConsoleLine> {
     alt((
         map(preceded(tag("$ cd "), not_line_ending), |path: &str| {
@@ -117,81 +343,508 @@ fn parse_instruction_list(input: &str) -> IResult<&str, Vec<ConsoleLine>> {
     separated_list1(line_ending, parse_instruction)(input)
 }
 
-fn build_directory_tree(input: Vec<ConsoleLine>) -> Result<Directory> {
-    let mut stack: Vec<Directory> = vec![];
-    for line in input {
-        match line {
+/// Something odd in the transcript, which `build_filesystem` works around
+#[derive(Debug, PartialEq, Eq)]
+enum Diagnostic {
+    /// `cd ..` from the root, which stays at the root
+    AboveRoot { line: usize },
+    /// `cd` into a directory no listing has shown, which is created
+    UnlistedDirectory { line: usize, path: String },
+    /// listing output without an `ls` before it, which is still added to the directory
+    OutputWithoutLs { line: usize },
+    /// a file listed again with a different size, the later size is kept
+    SizeConflict {
+        line: usize,
+        path: String,
+        old: usize,
+        new: usize,
+    },
+    /// a name that is both a file and a directory, the later one is kept
+    KindConflict { line: usize, path: String },
+    /// something an earlier `ls` showed that a later one of the same directory doesn't,
+    /// which is kept
+    MissingFromListing { line: usize, path: String },
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::AboveRoot { line } => write!(f, "line {line}: `cd ..` from /"),
+            Diagnostic::UnlistedDirectory { line, path } => {
+                write!(f, "line {line}: {path} was never listed")
+            }
+            Diagnostic::OutputWithoutLs { line } => write!(f, "line {line}: output without ls"),
+            Diagnostic::SizeConflict {
+                line,
+                path,
+                old,
+                new,
+            } => write!(f, "line {line}: {path} was {old} but is now {new}"),
+            Diagnostic::KindConflict { line, path } => {
+                write!(f, "line {line}: {path} is both a file and a directory")
+            }
+            Diagnostic::MissingFromListing { line, path } => {
+                write!(f, "line {line}: {path} is missing from this listing")
+            }
+        }
+    }
+}
+
+fn path_string(path: &[String]) -> String {
+    format!("/{}", path.join("/"))
+}
+
+fn child_path(path: &[String], name: &str) -> String {
+    match path {
+        [] => format!("/{name}"),
+        _ => format!("{}/{name}", path_string(path)),
+    }
+}
+
+/// The directory tree rebuilt from a transcript, with a note of anything odd in it
+#[derive(Debug, PartialEq, Eq)]
+struct FileSystem {
+    root: Directory,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// What's been seen so far while an `ls` is being read
+struct Listing {
+    line: usize,
+    path: Vec<String>,
+    seen: HashSet<String>,
+}
+
+impl FileSystem {
+    fn dir_mut(&mut self, path: &[String]) -> &mut Directory {
+        path.iter().fold(&mut self.root, |dir, name| {
+            dir.subdirectories
+                .get_mut(name)
+                .expect("paths are only made of directories that exist")
+        })
+    }
+
+    /// Adds `dir` to the directory at `path`, unless it's already there
+    fn add_directory(&mut self, line: usize, path: &[String], name: &str) {
+        let parent = self.dir_mut(path);
+        if parent.files.remove(name).is_some() {
+            self.diagnostics.push(Diagnostic::KindConflict {
+                line,
+                path: child_path(path, name),
+            });
+        }
+        self.dir_mut(path)
+            .subdirectories
+            .entry(name.to_owned())
+            .or_insert_with(|| Directory::new(name.to_owned()));
+    }
+
+    fn add_file(&mut self, line: usize, path: &[String], file: File) {
+        let full_path = child_path(path, &file.name);
+        let dir = self.dir_mut(path);
+        let conflict = if dir.subdirectories.remove(&file.name).is_some() {
+            Some(Diagnostic::KindConflict {
+                line,
+                path: full_path,
+            })
+        } else {
+            match dir.files.get(&file.name) {
+                Some(old) if old.size != file.size => Some(Diagnostic::SizeConflict {
+                    line,
+                    path: full_path,
+                    old: old.size,
+                    new: file.size,
+                }),
+                _ => None,
+            }
+        };
+        dir.files.insert(file.name.clone(), file);
+        self.diagnostics.extend(conflict);
+    }
+
+    /// Reports anything a repeated `ls` didn't show again
+    fn finish_listing(&mut self, listing: Listing, listed: &mut HashSet<Vec<String>>) {
+        if !listed.insert(listing.path.clone()) {
+            let dir = self.dir_mut(&listing.path);
+            let missing = dir
+                .files
+                .keys()
+                .chain(dir.subdirectories.keys())
+                .filter(|name| !listing.seen.contains(*name))
+                .sorted()
+                .map(|name| child_path(&listing.path, name))
+                .collect_vec();
+            self.diagnostics.extend(missing.into_iter().map(|path| {
+                Diagnostic::MissingFromListing {
+                    line: listing.line,
+                    path,
+                }
+            }));
+        }
+    }
+}
+
+/// Replays the transcript against a tree keyed by real paths. `cd` accepts `/`, `..`, and
+/// relative or absolute paths with several parts
+fn build_filesystem(input: Vec<ConsoleLine>) -> FileSystem {
+    let mut fs = FileSystem {
+        root: Directory::new("/".into()),
+        diagnostics: vec![],
+    };
+    let mut cwd: Vec<String> = vec![];
+    let mut listing: Option<Listing> = None;
+    let mut listed = HashSet::new();
+
+    for (i, console_line) in input.into_iter().enumerate() {
+        let line = i + 1;
+        if matches!(console_line, ConsoleLine::Cd(_) | ConsoleLine::Ls) {
+            if let Some(finished) = listing.take() {
+                fs.finish_listing(finished, &mut listed);
+            }
+        } else if listing.is_none() {
+            fs.diagnostics.push(Diagnostic::OutputWithoutLs { line });
+        }
+
+        match console_line {
             ConsoleLine::Cd(path) => {
-                if path == ".." {
-                    let last = stack.pop().context("cannot go up directory")?;
-                    let parent_dir = stack
-                        .last_mut()
-                        .context("stack empty")?
-                        .subdirectories
-                        .entry(last.name.clone())
-                        .or_insert_with(|| Directory::new(last.name.clone()));
-                    parent_dir.merge(last);
-                } else {
-                    let entry = stack
-                        .last_mut()
-                        .unwrap_or(&mut Directory::new(path.clone()))
-                        .subdirectories
-                        .remove(&path)
-                        .unwrap_or_else(|| Directory::new(path));
-                    stack.push(entry);
+                if path.starts_with('/') {
+                    cwd.clear();
+                }
+                for part in path
+                    .split('/')
+                    .filter(|part| !part.is_empty() && *part != ".")
+                {
+                    if part == ".." {
+                        if cwd.pop().is_none() {
+                            fs.diagnostics.push(Diagnostic::AboveRoot { line });
+                        }
+                        continue;
+                    }
+                    if !fs.dir_mut(&cwd).subdirectories.contains_key(part) {
+                        fs.diagnostics.push(Diagnostic::UnlistedDirectory {
+                            line,
+                            path: child_path(&cwd, part),
+                        });
+                        fs.add_directory(line, &cwd, part);
+                    }
+                    cwd.push(part.to_owned());
                 }
             }
-            ConsoleLine::Ls => {}
+            ConsoleLine::Ls => {
+                listing = Some(Listing {
+                    line,
+                    path: cwd.clone(),
+                    seen: HashSet::new(),
+                });
+            }
             ConsoleLine::File(file) => {
-                stack
-                    .last_mut()
-                    .context("no directory tree to add file to")?
-                    .files
-                    .insert(file.name.clone(), file);
+                if let Some(listing) = &mut listing {
+                    listing.seen.insert(file.name.clone());
+                }
+                fs.add_file(line, &cwd, file);
             }
             ConsoleLine::Directory(dir) => {
-                stack
-                    .last_mut()
-                    .context("no directory tree to add file to")?
-                    .subdirectories
-                    .insert(dir.name.clone(), dir);
+                if let Some(listing) = &mut listing {
+                    listing.seen.insert(dir.name.clone());
+                }
+                fs.add_directory(line, &cwd, &dir.name);
             }
         }
     }
-    while stack.len() > 1 {
-        let last = stack.pop().unwrap();
-        stack
-            .last_mut()
-            .unwrap()
-            .subdirectories
-            .insert(last.name.clone(), last);
+    if let Some(finished) = listing {
+        fs.finish_listing(finished, &mut listed);
     }
-    stack.pop().context("Empty instructions")
+    fs
 }
 
-fn part1(dir: &Directory) -> usize {
-    dir.walk_dirs()
-        .iter()
-        .map(|d| d.size())
-        .filter(|size| *size <= 100_000)
-        .sum()
+/// How big the disk is, how much of it has to be free, and how many paths a plan may
+/// delete at most. The search is exponential in that limit, so it defaults to 3, and
+/// plans say they're only the cheapest within it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DeletionPlanner {
+    disk: usize,
+    required: usize,
+    max_paths: usize,
 }
 
-fn part2(dir: &Directory) -> Option<usize> {
-    let target_size = dir.size().checked_sub(40_000_000)?;
-    dir.walk_dirs()
+impl Default for DeletionPlanner {
+    fn default() -> Self {
+        Self {
+            disk: 70_000_000,
+            required: 30_000_000,
+            max_paths: 3,
+        }
+    }
+}
+
+/// A set of paths to delete, and how much deleting them frees
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Deletion {
+    paths: Vec<String>,
+    freed: usize,
+}
+
+impl DeletionPlanner {
+    /// How much more space has to be freed, if any
+    fn needed(&self, usage: &DiskUsage) -> usize {
+        (usage.total() + self.required).saturating_sub(self.disk)
+    }
+
+    /// The `alternatives` cheapest sets of directories that free enough space, smallest
+    /// first. No directory in a set is inside another
+    fn directories(&self, usage: &DiskUsage, alternatives: usize) -> Vec<Deletion> {
+        let dirs = usage
+            .dirs
+            .iter()
+            .filter(|(path, _)| *path != "/")
+            .map(|(path, &size)| (path.as_str(), size))
+            .collect_vec();
+        cheapest_deletions(&dirs, self.needed(usage), self.max_paths, alternatives)
+    }
+
+    /// The `alternatives` cheapest sets of files that free enough space, smallest first
+    fn files(&self, usage: &DiskUsage, alternatives: usize) -> Vec<Deletion> {
+        let files = usage
+            .files
+            .iter()
+            .map(|(path, &size)| (path.as_str(), size))
+            .collect_vec();
+        cheapest_deletions(&files, self.needed(usage), self.max_paths, alternatives)
+    }
+
+    /// A shell script deleting everything in `deletion` from the tree under the directory
+    /// it's given, like one made by `materialise`
+    fn script(&self, usage: &DiskUsage, deletion: &Deletion) -> String {
+        let free = (self.disk + deletion.freed).saturating_sub(usage.total());
+        let plural = if self.max_paths == 1 { "" } else { "s" };
+        let mut script = format!(
+            "#!/bin/sh\n# cheapest deleting at most {} path{plural}\n# frees {}, leaving {free} of the {} needed\nROOT=\"${{1:?usage: $0 ROOT}}\"\n",
+            self.max_paths, deletion.freed, self.required
+        );
+        for path in &deletion.paths {
+            let flag = if usage.dirs.contains_key(path) {
+                "-r "
+            } else {
+                ""
+            };
+            script.push_str(&format!(
+                "rm {flag}\"$ROOT\"'{}'\n",
+                path.replace('\'', "'\\''")
+            ));
+        }
+        script
+    }
+}
+
+/// Whether `path` is `dir` or somewhere inside it
+fn is_within(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Searches for the sets of at most `max_paths` paths freeing at least `needed` with the
+/// least total size, never picking a path inside another picked path. Bigger paths are
+/// tried first, and a branch is dropped as soon as it can't free enough or can't beat the
+/// worst set kept
+fn cheapest_deletions(
+    candidates: &[(&str, usize)],
+    needed: usize,
+    max_paths: usize,
+    alternatives: usize,
+) -> Vec<Deletion> {
+    struct Search<'a> {
+        candidates: Vec<(&'a str, usize)>,
+        /// how much the first i candidates add up to
+        prefix: Vec<usize>,
+        needed: usize,
+        max_paths: usize,
+        alternatives: usize,
+        best: Vec<Deletion>,
+    }
+
+    impl Search<'_> {
+        fn visit(&mut self, i: usize, chosen: &mut Vec<usize>, freed: usize) {
+            if freed >= self.needed {
+                let deletion = Deletion {
+                    paths: chosen
+                        .iter()
+                        .map(|&c| self.candidates[c].0.to_owned())
+                        .sorted()
+                        .collect(),
+                    freed,
+                };
+                let at = self
+                    .best
+                    .partition_point(|d| (d.freed, d.paths.len()) <= (freed, chosen.len()));
+                self.best.insert(at, deletion);
+                self.best.truncate(self.alternatives);
+                return;
+            }
+            // the biggest this branch can still free is the next few candidates
+            let end = i.saturating_add(self.max_paths - chosen.len());
+            let most = self.prefix[end.min(self.candidates.len())] - self.prefix[i];
+            if chosen.len() == self.max_paths
+                || i == self.candidates.len()
+                || freed + most < self.needed
+                || (self.best.len() == self.alternatives
+                    && self.best.last().is_some_and(|worst| freed >= worst.freed))
+            {
+                return;
+            }
+
+            let (path, size) = self.candidates[i];
+            if !chosen.iter().any(|&c| {
+                let other = self.candidates[c].0;
+                is_within(path, other) || is_within(other, path)
+            }) {
+                chosen.push(i);
+                self.visit(i + 1, chosen, freed + size);
+                chosen.pop();
+            }
+            self.visit(i + 1, chosen, freed);
+        }
+    }
+
+    if alternatives == 0 {
+        return vec![];
+    }
+    let candidates = candidates
         .iter()
-        .map(|d| d.size())
+        .copied()
+        .sorted_by_key(|&(path, size)| (Reverse(size), path))
+        .collect_vec();
+    let prefix = std::iter::once(0)
+        .chain(candidates.iter().scan(0, |sum, &(_, size)| {
+            *sum += size;
+            Some(*sum)
+        }))
+        .collect();
+    let mut search = Search {
+        candidates,
+        prefix,
+        needed,
+        max_paths,
+        alternatives,
+        best: vec![],
+    };
+    search.visit(0, &mut vec![], 0);
+    search.best
+}
+
+fn part1(usage: &DiskUsage) -> usize {
+    usage.dirs.values().filter(|size| **size <= 100_000).sum()
+}
+
+fn part2(usage: &DiskUsage, planner: &DeletionPlanner) -> Option<usize> {
+    let target_size = planner.needed(usage);
+    if target_size == 0 {
+        // there's already enough space
+        return None;
+    }
+    usage
+        .dirs
+        .values()
+        .copied()
         .filter(|size| *size >= target_size)
         .min()
 }
 fn main() -> Result<()> {
     let input = fs::read_to_string("inputs/day7.txt").unwrap();
     let instructions = parse_instruction_list(&input).unwrap().1;
-    let dtree = build_directory_tree(instructions)?;
+    let fs = build_filesystem(instructions);
+    for diagnostic in &fs.diagnostics {
+        eprintln!("{diagnostic}");
+    }
+    let usage = DiskUsage::new(&fs.root);
 
-    println!("7.1: {}", part1(&dtree));
-    println!("7.2: {}", part2(&dtree).unwrap());
+    let args = std::env::args().skip(1).collect_vec();
+    match args.iter().map(String::as_str).collect_vec()[..] {
+        [] => {
+            println!("7.1: {}", part1(&usage));
+            println!(
+                "7.2: {}",
+                part2(&usage, &DeletionPlanner::default()).unwrap()
+            );
+        }
+        ["du"] | ["du", "-h"] => {
+            for (path, size) in usage.du() {
+                let size = match args.len() {
+                    1 => size.to_string(),
+                    _ => human_size(size),
+                };
+                println!("{size}\t{path}");
+            }
+        }
+        ["tree"] => println!("{}", usage.tree(&fs.root)),
+        ["find", "-size", size] => {
+            let size = size.strip_prefix('+').context("sizes look like +N")?;
+            for (path, size) in usage.find_larger(size.parse()?) {
+                println!("{size}\t{path}");
+            }
+        }
+        ["largest", n] => {
+            for (path, size) in usage.largest_files(n.parse()?) {
+                println!("{size}\t{path}");
+            }
+        }
+        ["plan", ref options @ ..] => {
+            let mut planner = DeletionPlanner::default();
+            let (mut files, mut alternatives) = (false, 1);
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                let mut value =
+                    || -> Result<usize> { Ok(options.next().context("missing value")?.parse()?) };
+                match *option {
+                    "--files" => files = true,
+                    "--disk" => planner.disk = value()?,
+                    "--required" => planner.required = value()?,
+                    "--alternatives" => alternatives = value()?,
+                    "--max-paths" => planner.max_paths = value()?,
+                    _ => bail!("unknown option {option}"),
+                }
+            }
+            let plans = match files {
+                true => planner.files(&usage, alternatives),
+                false => planner.directories(&usage, alternatives),
+            };
+            if plans.is_empty() {
+                bail!(
+                    "no set of at most {} paths frees enough space",
+                    planner.max_paths
+                );
+            }
+            for plan in plans {
+                println!("{}", planner.script(&usage, &plan));
+            }
+        }
+        ["export", "json"] => println!("{}", to_json(&fs.root, &usage, &mut vec![])),
+        ["export", "csv"] => print!("{}", to_csv(&usage)),
+        ["materialise", at] => {
+            let at = Path::new(at);
+            materialise(&fs.root, at)
+                .with_context(|| format!("couldn't materialise into {}", at.display()))?;
+            let on_disk = DiskUsage::new(&read_tree("/".into(), at)?);
+            let planner = DeletionPlanner::default();
+            let answers = (part1(&usage), part2(&usage, &planner));
+            let from_disk = (part1(&on_disk), part2(&on_disk, &planner));
+            if on_disk != usage || answers != from_disk {
+                bail!("{} doesn't match the transcript", at.display());
+            }
+            println!(
+                "materialised {} files in {} directories under {}",
+                usage.files.len(),
+                usage.dirs.len(),
+                at.display()
+            );
+            println!("7.1: {}", from_disk.0);
+            println!("7.2: {}", from_disk.1.unwrap());
+        }
+        _ => bail!(
+            "usage: day7 [du [-h] | tree | find -size +N | largest N | plan [--files] \
+             [--disk N] [--required N] [--max-paths N] [--alternatives N] | \
+             export json | export csv | materialise DIR]"
+        ),
+    }
     Ok(())
 }
 
@@ -202,17 +855,288 @@ mod tests {
     fn test_part1() {
         let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
         let instructions = parse_instruction_list(&input).unwrap().1;
-        let dtree = build_directory_tree(instructions).unwrap();
+        let dtree = build_filesystem(instructions).root;
 
-        assert_eq!(part1(&dtree), 95437);
+        assert_eq!(part1(&DiskUsage::new(&dtree)), 95437);
     }
 
     #[test]
     fn test_part2() {
         let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
         let instructions = parse_instruction_list(&input).unwrap().1;
-        let dtree = build_directory_tree(instructions).unwrap();
+        let dtree = build_filesystem(instructions).root;
+
+        assert_eq!(
+            part2(&DiskUsage::new(&dtree), &DeletionPlanner::default()).unwrap(),
+            24933642
+        );
+        let planner = DeletionPlanner {
+            disk: 100_000_000,
+            ..DeletionPlanner::default()
+        };
+        assert_eq!(part2(&DiskUsage::new(&dtree), &planner), None);
+    }
+
+    #[test]
+    fn test_no_diagnostics() {
+        let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
+        let instructions = parse_instruction_list(&input).unwrap().1;
+        assert_eq!(build_filesystem(instructions).diagnostics, vec![]);
+    }
+
+    #[test]
+    fn test_paths() {
+        let input = "$ cd /\n$ ls\ndir a\n1 x\n$ cd a\n$ ls\ndir b\n$ cd b\n$ ls\n2 y\n\
+                     $ cd /\n$ cd a/b\n$ ls\n2 y\n3 z\n$ cd /a\n$ cd ../a/b/../..\n$ ls\n1 x";
+        let instructions = parse_instruction_list(input).unwrap().1;
+        let fs = build_filesystem(instructions);
+        assert_eq!(DiskUsage::new(&fs.root).total(), 6);
+        assert_eq!(
+            fs.root.subdirectories["a"].subdirectories["b"].files.len(),
+            2
+        );
+        assert_eq!(
+            fs.diagnostics,
+            vec![Diagnostic::MissingFromListing {
+                line: 18,
+                path: "/a".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let input = "$ cd ..\n$ ls\n1 x\n2 x\ndir y\n$ cd z\n$ cd /\n$ ls\n4 y\n$ cd /\n5 w";
+        let instructions = parse_instruction_list(input).unwrap().1;
+        let fs = build_filesystem(instructions);
+        assert_eq!(
+            fs.diagnostics,
+            vec![
+                Diagnostic::AboveRoot { line: 1 },
+                Diagnostic::SizeConflict {
+                    line: 4,
+                    path: "/x".into(),
+                    old: 1,
+                    new: 2
+                },
+                Diagnostic::UnlistedDirectory {
+                    line: 6,
+                    path: "/z".into()
+                },
+                Diagnostic::KindConflict {
+                    line: 9,
+                    path: "/y".into()
+                },
+                Diagnostic::MissingFromListing {
+                    line: 8,
+                    path: "/x".into()
+                },
+                Diagnostic::MissingFromListing {
+                    line: 8,
+                    path: "/z".into()
+                },
+                Diagnostic::OutputWithoutLs { line: 11 },
+            ]
+        );
+        assert_eq!(DiskUsage::new(&fs.root).total(), 11);
+    }
+
+    #[test]
+    fn test_queries() {
+        let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
+        let instructions = parse_instruction_list(&input).unwrap().1;
+        let root = build_filesystem(instructions).root;
+        let usage = DiskUsage::new(&root);
+
+        assert_eq!(
+            usage.du(),
+            vec![
+                ("/", 48381165),
+                ("/d", 24933642),
+                ("/a", 94853),
+                ("/a/e", 584)
+            ]
+        );
+        assert_eq!(
+            usage.find_larger(8_100_000),
+            vec![
+                ("/", 48381165),
+                ("/b.txt", 14848514),
+                ("/c.dat", 8504156),
+                ("/d", 24933642)
+            ]
+        );
+        assert_eq!(
+            usage.largest_files(2),
+            vec![("/b.txt", 14848514), ("/c.dat", 8504156)]
+        );
+        assert_eq!(
+            usage.tree(&root),
+            "/ (48381165)
+├── a/ (94853)
+│   ├── e/ (584)
+│   │   └── i (584)
+│   ├── f (29116)
+│   ├── g (2557)
+│   └── h.lst (62596)
+├── b.txt (14848514)
+├── c.dat (8504156)
+└── d/ (24933642)
+    ├── d.ext (5626152)
+    ├── d.log (8033020)
+    ├── j (4060174)
+    └── k (7214296)"
+        );
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(584), "584");
+        assert_eq!(human_size(1024), "1.0K");
+        assert_eq!(human_size(94853), "93K");
+        assert_eq!(human_size(8504156), "8.2M");
+        assert_eq!(human_size(48381165), "47M");
+        assert_eq!(human_size(10239), "10K");
+        assert_eq!(human_size(1048575), "1.0M");
+        assert_eq!(human_size(1048000), "1.0M");
+        assert_eq!(human_size(1047552), "1023K");
+        assert_eq!(human_size(1024 * 1024 * 1024 - 1), "1.0G");
+    }
+
+    #[test]
+    fn test_deletion_planner() {
+        let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
+        let instructions = parse_instruction_list(&input).unwrap().1;
+        let usage = DiskUsage::new(&build_filesystem(instructions).root);
+
+        let planner = DeletionPlanner::default();
+        assert_eq!(planner.needed(&usage), 8381165);
+        let plans = planner.directories(&usage, 3);
+        assert_eq!(
+            plans,
+            vec![Deletion {
+                paths: vec!["/d".into()],
+                freed: 24933642
+            }]
+        );
+        let plans = planner.files(&usage, 2);
+        assert_eq!(
+            plans,
+            vec![
+                Deletion {
+                    paths: vec!["/c.dat".into()],
+                    freed: 8504156
+                },
+                Deletion {
+                    paths: vec!["/d/d.ext".into(), "/d/j".into()],
+                    freed: 9686326
+                },
+            ]
+        );
+        assert_eq!(
+            planner.script(&usage, &plans[0]),
+            "#!/bin/sh\n# cheapest deleting at most 3 paths\n# frees 8504156, leaving 30122991 of the 30000000 needed\nROOT=\"${1:?usage: $0 ROOT}\"\nrm \"$ROOT\"'/c.dat'\n"
+        );
+
+        // a disk smaller than the tree on it
+        let planner = DeletionPlanner {
+            disk: 40_000_000,
+            required: 0,
+            ..DeletionPlanner::default()
+        };
+        assert_eq!(planner.needed(&usage), 8381165);
+        let plans = planner.files(&usage, 1);
+        assert_eq!(
+            planner.script(&usage, &plans[0]).lines().nth(2),
+            Some("# frees 8504156, leaving 122991 of the 0 needed")
+        );
+
+        let planner = DeletionPlanner {
+            disk: 48_400_000,
+            required: 100_000,
+            max_paths: 1,
+        };
+        let plans = planner.directories(&usage, 2);
+        assert_eq!(plans[0].paths, vec!["/a".to_owned()]);
+        assert_eq!(plans[1].paths, vec!["/d".to_owned()]);
+        assert_eq!(
+            planner.script(&usage, &plans[0]).lines().last(),
+            Some("rm -r \"$ROOT\"'/a'")
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let input = "$ cd /\n$ ls\ndir a\n10 x,y\n$ cd a\n$ ls\n5 \"q\"";
+        let instructions = parse_instruction_list(input).unwrap().1;
+        let root = build_filesystem(instructions).root;
+        let usage = DiskUsage::new(&root);
+
+        assert_eq!(
+            to_json(&root, &usage, &mut vec![]),
+            r#"{"name":"/","size":15,"files":[{"name":"x,y","size":10}],"directories":[{"name":"a","size":5,"files":[{"name":"\"q\"","size":5}],"directories":[]}]}"#
+        );
+        assert_eq!(
+            to_csv(&usage),
+            "path,kind,size\n/,dir,15\n/a,dir,5\n\"/a/\"\"q\"\"\",file,5\n\"/x,y\",file,10\n"
+        );
+    }
+
+    #[test]
+    fn test_materialise() {
+        let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
+        let instructions = parse_instruction_list(&input).unwrap().1;
+        let root = build_filesystem(instructions).root;
+
+        let at = std::env::temp_dir().join(format!("day7-{}", std::process::id()));
+        materialise(&root, &at).unwrap();
+        let on_disk = read_tree("/".into(), &at).unwrap();
+        fs::remove_dir_all(&at).unwrap();
+        assert_eq!(on_disk, root);
+
+        let mut root = root;
+        root.files.insert(
+            "..".into(),
+            File {
+                size: 1,
+                name: "..".into(),
+            },
+        );
+        assert!(materialise(&root, &at).is_err());
+        fs::remove_dir_all(&at).unwrap();
+    }
+
+    #[test]
+    fn test_plan_script() {
+        let input = fs::read_to_string("test_inputs/day7.txt").unwrap();
+        let instructions = parse_instruction_list(&input).unwrap().1;
+        let root = build_filesystem(instructions).root;
+        let usage = DiskUsage::new(&root);
+        let planner = DeletionPlanner::default();
+
+        let at = std::env::temp_dir().join(format!("day7-plan-{}", std::process::id()));
+        materialise(&root, &at).unwrap();
+        let script = planner.script(&usage, &planner.files(&usage, 2)[1]);
+        assert!(script.ends_with(
+            "ROOT=\"${1:?usage: $0 ROOT}\"\nrm \"$ROOT\"'/d/d.ext'\nrm \"$ROOT\"'/d/j'\n"
+        ));
+        let status = std::process::Command::new("sh")
+            .args(["-c", &script, "plan"])
+            .arg(&at)
+            .status()
+            .unwrap();
+        let left = DiskUsage::new(&read_tree("/".into(), &at).unwrap());
+        fs::remove_dir_all(&at).unwrap();
+        assert!(status.success());
+        assert_eq!(left.total(), usage.total() - 9686326);
+        assert!(!left.files.contains_key("/d/j"));
 
-        assert_eq!(part2(&dtree).unwrap(), 24933642);
+        // without a root it refuses to do anything
+        let status = std::process::Command::new("sh")
+            .args(["-c", &script])
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success());
     }
 }
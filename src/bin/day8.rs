@@ -1,59 +1,151 @@
 use std::fs;
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+enum ForestError {
+    #[error("the forest is empty")]
+    Empty,
+    #[error("row {row} has {width} trees but the first row has {expected}")]
+    Ragged {
+        row: usize,
+        width: usize,
+        expected: usize,
+    },
+    #[error("{token:?} in row {row} isn't a tree height")]
+    InvalidHeight { row: usize, token: String },
+}
+
+/// A grid of tree heights, stored row by row. Rows are either a string of digits or
+/// heights separated by whitespace, so heights aren't limited to a single digit
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Forest {
+    width: usize,
+    height: usize,
+    trees: Vec<u32>,
+}
+
+impl FromStr for Forest {
+    type Err = ForestError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let mut trees = vec![];
+        let mut width = None;
+        let mut height = 0;
+        for (row, line) in input.lines().map(str::trim).enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let before = trees.len();
+            if line.contains(char::is_whitespace) {
+                for token in line.split_whitespace() {
+                    trees.push(token.parse().map_err(|_| ForestError::InvalidHeight {
+                        row,
+                        token: token.to_owned(),
+                    })?);
+                }
+            } else {
+                for c in line.chars() {
+                    trees.push(c.to_digit(10).ok_or(ForestError::InvalidHeight {
+                        row,
+                        token: c.to_string(),
+                    })?);
+                }
+            }
+            let row_width = trees.len() - before;
+            match width {
+                None => width = Some(row_width),
+                Some(expected) if expected != row_width => {
+                    return Err(ForestError::Ragged {
+                        row,
+                        width: row_width,
+                        expected,
+                    })
+                }
+                Some(_) => {}
+            }
+            height += 1;
+        }
+        match width {
+            Some(width) if width > 0 => Ok(Self {
+                width,
+                height,
+                trees,
+            }),
+            _ => Err(ForestError::Empty),
+        }
+    }
+}
+
+impl Forest {
+    fn get(&self, x: usize, y: usize) -> Option<u32> {
+        (x < self.width && y < self.height).then(|| self.trees[y * self.width + x])
+    }
+
+    fn rows(&self) -> impl Iterator<Item = &[u32]> {
+        self.trees.chunks(self.width)
+    }
+}
 
 /// Traverses the forest exactly once
 /// uses two pointers that converge for the left and right trees
-/// uses a [u8; N] for the tallest tree visible in column i from the top
-/// uses the lower 10 bits of a [u16; N] to store the trees visible from the bottom in columnn i
-fn part1<const N: usize>(forest: &[&[u8]]) -> u32 {
-    let mut bottom_edge_bitvec = [0; N]; //bitmask for trees visible from the bottom
-    let mut top_edge_tallest = [0u8; N]; //max values visible from above
+/// uses a Vec with the tallest tree visible in column i from the top
+/// uses a stack per column of the trees that could still be visible from the bottom,
+/// tallest at the bottom of the stack, so a new tree pops everything it hides
+fn part1(forest: &Forest) -> usize {
+    let n = forest.width;
+    let mut bottom_edge_stacks: Vec<Vec<u32>> = vec![vec![]; n]; //trees visible from the bottom
+    let mut top_edge_tallest: Vec<Option<u32>> = vec![None; n]; //max values visible from above
     let [mut front_index, mut back_index]: [usize; 2];
-    let [mut front_tree, mut current_tree, mut back_tree]: [u8; 3];
+    let [mut front_tree, mut back_tree]: [Option<u32>; 2];
+    let mut current_tree: u32;
     let mut is_edge: bool;
     let mut edge_count = 0;
 
-    for treeline in forest {
-        (front_index, back_index, front_tree, back_tree) = (0, N - 1, b'0' - 1, b'0' - 1);
+    for treeline in forest.rows() {
+        (front_index, back_index, front_tree, back_tree) = (0, n - 1, None, None);
 
         // move the pointers together till they kiss
         // we know in advance that we'll only have to do N moves
-        for _ in 0..N {
+        for _ in 0..n {
             is_edge = false;
-            if front_tree <= back_tree {
+            let index = if front_tree <= back_tree {
                 current_tree = treeline[front_index];
 
                 //front check
-                if current_tree > front_tree {
-                    is_edge = true;
-                    front_tree = current_tree;
-                }
-
-                //top check
-                if current_tree > top_edge_tallest[front_index] {
+                if Some(current_tree) > front_tree {
                     is_edge = true;
-                    top_edge_tallest[front_index] = current_tree;
+                    front_tree = Some(current_tree);
                 }
-
-                //bottom check
-                bottom_edge_bitvec[front_index] &= !(1u16) << (current_tree - b'0'); //zero out all trees at or below current height
-                bottom_edge_bitvec[front_index] |= (!is_edge as u16) << (current_tree - b'0'); //if !include, add back in tree at current height
-
                 front_index += 1;
+                front_index - 1
             } else {
                 // same logic but for back of list
                 current_tree = treeline[back_index];
-                if current_tree > back_tree {
+                if Some(current_tree) > back_tree {
                     is_edge = true;
-                    back_tree = current_tree;
-                }
-                if current_tree > top_edge_tallest[back_index] {
-                    is_edge = true;
-                    top_edge_tallest[back_index] = current_tree;
+                    back_tree = Some(current_tree);
                 }
+                back_index = back_index.saturating_sub(1);
+                back_index + 1
+            };
 
-                bottom_edge_bitvec[back_index] &= !(1u16) << (current_tree - b'0');
-                bottom_edge_bitvec[back_index] |= (!is_edge as u16) << (current_tree - b'0');
-                back_index -= 1;
+            //top check
+            if Some(current_tree) > top_edge_tallest[index] {
+                is_edge = true;
+                top_edge_tallest[index] = Some(current_tree);
+            }
+
+            //bottom check
+            let stack = &mut bottom_edge_stacks[index];
+            while stack.last().is_some_and(|&tree| tree <= current_tree) {
+                stack.pop(); //hidden from the bottom by the current tree
+            }
+            if !is_edge {
+                stack.push(current_tree);
             }
 
             if is_edge {
@@ -61,77 +153,454 @@ fn part1<const N: usize>(forest: &[&[u8]]) -> u32 {
             }
         }
     }
-    bottom_edge_bitvec
-        .into_iter()
-        .map(|x| x.count_ones())
-        .sum::<u32>()
-        + edge_count
+    bottom_edge_stacks.iter().map(Vec::len).sum::<usize>() + edge_count
 }
 
-fn scenic_score(rows: &[&[u8]], (start_x, start_y): (isize, isize)) -> isize {
-    let height = rows[start_y as usize][start_x as usize];
+/// The edges of the forest a tree can be seen from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Edges(u8);
 
-    [(1, 0), (-1, 0), (0, 1), (0, -1)]
-        .into_iter()
-        .map(|(dx, dy)| {
-            let (mut x, mut y) = (start_x + dx, start_y + dy);
-            while let Some(&tree) = rows.get(y as usize).and_then(|row| row.get(x as usize)) {
-                (x, y) = (x + dx, y + dy);
-                if tree >= height {
-                    break;
-                }
-            }
-            (x.abs_diff(start_x) + y.abs_diff(start_y) - 1) as isize
-        })
-        .product()
+impl Edges {
+    const TOP: Edges = Edges(1);
+    const BOTTOM: Edges = Edges(2);
+    const LEFT: Edges = Edges(4);
+    const RIGHT: Edges = Edges(8);
+
+    fn insert(&mut self, edge: Edges) {
+        self.0 |= edge.0;
+    }
+
+    fn contains(self, edge: Edges) -> bool {
+        self.0 & edge.0 == edge.0
+    }
+
+    fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// `.` for hidden trees, an arrow pointing away from the only edge a tree can be
+    /// seen from, or `+` for trees seen from several
+    fn symbol(self) -> char {
+        match self {
+            Edges(0) => '.',
+            Edges::TOP => 'v',
+            Edges::BOTTOM => '^',
+            Edges::LEFT => '>',
+            Edges::RIGHT => '<',
+            _ => '+',
+        }
+    }
+}
+
+/// Which edges every tree can be seen from, row by row
+#[derive(Debug, PartialEq, Eq)]
+struct Visibility {
+    width: usize,
+    edges: Vec<Edges>,
+}
+
+impl Visibility {
+    fn count(&self) -> usize {
+        self.edges.iter().filter(|edges| !edges.is_empty()).count()
+    }
+
+    fn render(&self) -> String {
+        self.edges
+            .chunks(self.width)
+            .map(|row| row.iter().map(|edges| edges.symbol()).collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Marks the trees along a line that are taller than everything before them
+fn mark_visible(
+    trees: &[u32],
+    line: impl Iterator<Item = usize>,
+    edges: &mut [Edges],
+    edge: Edges,
+) {
+    let mut tallest = None;
+    for index in line {
+        if Some(trees[index]) > tallest {
+            tallest = Some(trees[index]);
+            edges[index].insert(edge);
+        }
+    }
+}
+
+fn visibility(forest: &Forest) -> Visibility {
+    let (width, height, trees) = (forest.width, forest.height, &forest.trees);
+    let mut edges = vec![Edges::default(); trees.len()];
+    for y in 0..height {
+        let row = y * width..(y + 1) * width;
+        mark_visible(trees, row.clone(), &mut edges, Edges::LEFT);
+        mark_visible(trees, row.rev(), &mut edges, Edges::RIGHT);
+    }
+    for x in 0..width {
+        let column = (0..height).map(|y| y * width + x);
+        mark_visible(trees, column.clone(), &mut edges, Edges::TOP);
+        mark_visible(trees, column.rev(), &mut edges, Edges::BOTTOM);
+    }
+    Visibility { width, edges }
+}
+
+/// Whether nothing blocks the straight line from `a` to `b`, each an `(x, y, height)`.
+/// The line is followed one cell at a time along its longer axis, and a tree in the way
+/// blocks it if the tree reaches the line's height there. Cells outside the forest are
+/// empty, so either end can be off the grid
+fn clear_line(
+    forest: &Forest,
+    (ax, ay, ah): (isize, isize, u32),
+    (bx, by, bh): (isize, isize, u32),
+) -> bool {
+    let (dx, dy) = (bx - ax, by - ay);
+    let steps = dx.abs().max(dy.abs());
+    // rounds `d * k / steps` to the nearest cell
+    let offset = |d: isize, k: isize| (2 * d * k + steps * d.signum()) / (2 * steps);
+    (1..steps).all(|k| {
+        let (x, y) = (ax + offset(dx, k), ay + offset(dy, k));
+        let Some(tree) = usize::try_from(x)
+            .ok()
+            .zip(usize::try_from(y).ok())
+            .and_then(|(x, y)| forest.get(x, y))
+        else {
+            return true;
+        };
+        // compare tree < ah + (bh - ah) * k / steps without dividing
+        let line = ah as i64 * steps as i64 + (bh as i64 - ah as i64) * k as i64;
+        (tree as i64) * (steps as i64) < line
+    })
+}
+
+/// Whether the tops of two trees can see each other
+fn line_of_sight(forest: &Forest, (ax, ay): (usize, usize), (bx, by): (usize, usize)) -> bool {
+    let a = (ax as isize, ay as isize, forest.get(ax, ay).unwrap());
+    let b = (bx as isize, by as isize, forest.get(bx, by).unwrap());
+    clear_line(forest, a, b)
+}
+
+/// Someone standing outside the forest with their eyes at a given height
+#[derive(Debug, Clone, Copy)]
+struct Observer {
+    x: isize,
+    y: isize,
+    eye: u32,
 }
 
-fn part2(rows: &[&[u8]]) -> Option<isize> {
-    (0..rows.len())
-        .flat_map(|y| (0..rows[y].len()).map(move |x| scenic_score(rows, (x as isize, y as isize))))
-        .max()
+impl Observer {
+    /// `None` if `(x, y)` is inside the forest: observers have to stand outside it
+    fn new(forest: &Forest, x: isize, y: isize, eye: u32) -> Option<Self> {
+        let inside =
+            (0..forest.width as isize).contains(&x) && (0..forest.height as isize).contains(&y);
+        (!inside).then_some(Self { x, y, eye })
+    }
+
+    /// Whether the observer can see the top of the tree at `(x, y)`
+    fn sees(&self, forest: &Forest, (x, y): (usize, usize)) -> bool {
+        let target = (x as isize, y as isize, forest.get(x, y).unwrap());
+        clear_line(forest, (self.x, self.y, self.eye), target)
+    }
+
+    /// The forest with `#` for the trees the observer can see and `.` for the rest
+    fn render(&self, forest: &Forest) -> String {
+        (0..forest.height)
+            .map(|y| {
+                (0..forest.width)
+                    .map(|x| if self.sees(forest, (x, y)) { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Every tree's scenic score, row by row, and where the best one is
+#[derive(Debug, PartialEq, Eq)]
+struct ScenicScores {
+    width: usize,
+    scores: Vec<usize>,
+    best: (usize, usize),
+}
+
+impl ScenicScores {
+    fn get(&self, x: usize, y: usize) -> usize {
+        self.scores[y * self.width + x]
+    }
+
+    fn best_score(&self) -> usize {
+        self.get(self.best.0, self.best.1)
+    }
+}
+
+/// Multiplies each tree's score by how far it can see back towards the start of the
+/// line. The stack holds the trees that could still block the view, tallest at the
+/// bottom, so every tree is pushed and popped at most once
+fn look_along(
+    trees: &[u32],
+    line: impl Iterator<Item = usize>,
+    scores: &mut [usize],
+    stack: &mut Vec<(usize, u32)>,
+) {
+    stack.clear();
+    for (step, index) in line.enumerate() {
+        let tree = trees[index];
+        while stack.last().is_some_and(|&(_, other)| other < tree) {
+            stack.pop();
+        }
+        scores[index] *= match stack.last() {
+            Some(&(blocker, _)) => step - blocker,
+            None => step,
+        };
+        stack.push((step, tree));
+    }
 }
-fn main() {
-    let input: Vec<u8> = fs::read("inputs/day8.txt")
-        .unwrap()
-        .into_iter()
-        .filter(|x| *x != b'\r')
-        .collect();
-    let lines: Vec<&[u8]> = input.split(|&x| x == b'\n').collect();
+
+/// Works out every scenic score in O(width × height), looking along each row and column
+/// in both directions
+fn scenic_scores(forest: &Forest) -> ScenicScores {
+    let (width, height, trees) = (forest.width, forest.height, &forest.trees);
+    let mut scores = vec![1; trees.len()];
+    let mut stack = vec![];
+    for y in 0..height {
+        let row = y * width..(y + 1) * width;
+        look_along(trees, row.clone(), &mut scores, &mut stack);
+        look_along(trees, row.rev(), &mut scores, &mut stack);
+    }
+    for x in 0..width {
+        let column = (0..height).map(|y| y * width + x);
+        look_along(trees, column.clone(), &mut scores, &mut stack);
+        look_along(trees, column.rev(), &mut scores, &mut stack);
+    }
+    let best = (0..scores.len())
+        .rev()
+        .max_by_key(|&i| scores[i])
+        .unwrap_or(0);
+    ScenicScores {
+        width,
+        scores,
+        best: (best % width, best / width),
+    }
+}
+
+fn part2(forest: &Forest) -> usize {
+    scenic_scores(forest).best_score()
+}
+fn main() -> anyhow::Result<()> {
+    let forest: Forest = fs::read_to_string("inputs/day8.txt")?.parse()?;
 
     let time = std::time::Instant::now();
-    println!("8.1: {}", part1::<99>(&lines));
+    println!("8.1: {}", part1(&forest));
     println!("{:?}", time.elapsed());
     let time = std::time::Instant::now();
-    println!("8.2: {}", part2(&lines).unwrap());
+    println!("8.2: {}", part2(&forest));
     println!("{:?}", time.elapsed());
+    let scores = scenic_scores(&forest);
+    let (x, y) = scores.best;
+    println!(
+        "the best tree is at ({x}, {y}) and is {} tall",
+        forest.get(x, y).unwrap()
+    );
+
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut args = args.iter().map(String::as_str);
+    let value = |args: &mut dyn Iterator<Item = &str>| -> anyhow::Result<isize> {
+        Ok(args.next().context("missing a number")?.parse()?)
+    };
+    while let Some(arg) = args.next() {
+        match arg {
+            "--map" => {
+                let visibility = visibility(&forest);
+                println!("{}", visibility.render());
+                let from = |edge| visibility.edges.iter().filter(|e| e.contains(edge)).count();
+                println!(
+                    "{} trees visible: {} from the top, {} from the bottom, {} from the left, \
+                     {} from the right",
+                    visibility.count(),
+                    from(Edges::TOP),
+                    from(Edges::BOTTOM),
+                    from(Edges::LEFT),
+                    from(Edges::RIGHT)
+                );
+            }
+            "--observer" => {
+                let (x, y, eye) = (value(&mut args)?, value(&mut args)?, value(&mut args)?);
+                let observer = Observer::new(&forest, x, y, eye.try_into()?)
+                    .context("the observer has to stand outside the forest")?;
+                println!("{}", observer.render(&forest));
+            }
+            "--sight" => {
+                let a = (value(&mut args)? as usize, value(&mut args)? as usize);
+                let b = (value(&mut args)? as usize, value(&mut args)? as usize);
+                if forest.get(a.0, a.1).is_none() || forest.get(b.0, b.1).is_none() {
+                    bail!("both trees have to be in the forest");
+                }
+                let verb = if line_of_sight(&forest, a, b) {
+                    "can"
+                } else {
+                    "can't"
+                };
+                println!("{a:?} {verb} see {b:?}");
+            }
+            _ => bail!("usage: day8 [--map] [--observer X Y EYE] [--sight X1 Y1 X2 Y2]"),
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[test]
-    fn test_part1() {
-        let input: Vec<u8> = fs::read("test_inputs/day8.txt")
+    fn test_forest() -> Forest {
+        fs::read_to_string("test_inputs/day8.txt")
             .unwrap()
-            .into_iter()
-            .filter(|x| *x != b'\r')
-            .collect();
-        let lines: Vec<&[u8]> = input.split(|&x| x == b'\n').collect();
+            .parse()
+            .unwrap()
+    }
 
-        assert_eq!(21, part1::<5>(&lines));
+    #[test]
+    fn test_part1() {
+        assert_eq!(21, part1(&test_forest()));
     }
 
     #[test]
     fn test_part2() {
-        let input: Vec<u8> = fs::read("test_inputs/day8.txt")
-            .unwrap()
+        assert_eq!(8, part2(&test_forest()));
+    }
+
+    #[test]
+    fn test_parse() {
+        let forest: Forest = "10 2 30\n4 50 6\n".parse().unwrap();
+        assert_eq!((forest.width, forest.height), (3, 2));
+        assert_eq!(forest.get(1, 1), Some(50));
+        assert_eq!(forest.get(3, 0), None);
+        assert_eq!(
+            "123\n45".parse::<Forest>(),
+            Err(ForestError::Ragged {
+                row: 1,
+                width: 2,
+                expected: 3
+            })
+        );
+        assert_eq!(
+            "12a".parse::<Forest>(),
+            Err(ForestError::InvalidHeight {
+                row: 0,
+                token: "a".into()
+            })
+        );
+        assert_eq!("\n".parse::<Forest>(), Err(ForestError::Empty));
+    }
+
+    #[test]
+    fn test_tall_and_odd_shapes() {
+        // heights past 9 still work, and so do forests that aren't square
+        let forest: Forest = "5 5 5 5\n5 11 10 5\n5 12 3 5\n5 5 5 5".parse().unwrap();
+        assert_eq!(part1(&forest), 15);
+        let forest: Forest = "1".parse().unwrap();
+        assert_eq!(part1(&forest), 1);
+        let forest: Forest = "3\n1\n2".parse().unwrap();
+        assert_eq!(part1(&forest), 3);
+    }
+
+    /// Walks out from the tree in each direction, for checking the fast version against
+    fn scenic_score(forest: &Forest, (start_x, start_y): (isize, isize)) -> usize {
+        let height = forest.get(start_x as usize, start_y as usize).unwrap();
+
+        [(1, 0), (-1, 0), (0, 1), (0, -1)]
             .into_iter()
-            .filter(|x| *x != b'\r')
+            .map(|(dx, dy)| {
+                let (mut x, mut y) = (start_x + dx, start_y + dy);
+                while let Some(tree) = forest.get(x as usize, y as usize) {
+                    (x, y) = (x + dx, y + dy);
+                    if tree >= height {
+                        break;
+                    }
+                }
+                x.abs_diff(start_x) + y.abs_diff(start_y) - 1
+            })
+            .product()
+    }
+
+    #[test]
+    fn test_scenic_scores() {
+        let scores = scenic_scores(&test_forest());
+        assert_eq!(scores.best, (2, 3));
+        assert_eq!(scores.get(2, 1), 4);
+        assert_eq!(scores.get(0, 0), 0);
+
+        // a bumpy forest with plenty of equal heights, taller than it is wide
+        let mut seed = 7u32;
+        let trees = (0..11 * 7)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) % 6
+            })
             .collect();
-        let lines: Vec<&[u8]> = input.split(|&x| x == b'\n').collect();
+        let forest = Forest {
+            width: 7,
+            height: 11,
+            trees,
+        };
+        let scores = scenic_scores(&forest);
+        for y in 0..forest.height {
+            for x in 0..forest.width {
+                let expected = scenic_score(&forest, (x as isize, y as isize));
+                assert_eq!(scores.get(x, y), expected, "({x}, {y})");
+            }
+        }
+        assert_eq!(scores.best_score(), *scores.scores.iter().max().unwrap());
+    }
+
+    #[test]
+    fn test_visibility() {
+        let forest = test_forest();
+        let visibility = visibility(&forest);
+        assert_eq!(visibility.count(), part1(&forest));
+        assert_eq!(visibility.render(), "+vv++\n>++.<\n+<.<<\n>.+.+\n++^++");
+        assert!(visibility.edges[6].contains(Edges::TOP));
+        assert!(visibility.edges[6].contains(Edges::LEFT));
+        assert!(!visibility.edges[6].contains(Edges::RIGHT));
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let forest = test_forest();
+        // neighbours can always see each other
+        assert!(line_of_sight(&forest, (0, 0), (1, 0)));
+        // the 7 between them is in the way
+        assert!(!line_of_sight(&forest, (0, 0), (4, 0)));
+        assert!(line_of_sight(&forest, (3, 0), (3, 4)));
+        assert!(!line_of_sight(&forest, (0, 0), (4, 4)));
+        assert!(line_of_sight(&forest, (2, 1), (4, 2)));
+
+        // looking straight along a row at a tree's own height is the same as asking
+        // whether it's visible from that edge
+        let visibility = visibility(&forest);
+        for y in 0..forest.height {
+            for x in 0..forest.width {
+                let eye = forest.get(x, y).unwrap();
+                let observer = Observer {
+                    x: -1,
+                    y: y as isize,
+                    eye,
+                };
+                assert_eq!(
+                    observer.sees(&forest, (x, y)),
+                    visibility.edges[y * forest.width + x].contains(Edges::LEFT),
+                    "({x}, {y})"
+                );
+            }
+        }
 
-        assert_eq!(Some(8), part2(&lines));
+        assert!(Observer::new(&forest, 4, 4, 9).is_none());
+        assert!(Observer::new(&forest, 0, 0, 9).is_none());
+        assert!(Observer::new(&forest, 5, 4, 9).is_some());
+        let observer = Observer::new(&forest, 2, -3, 9).unwrap();
+        assert_eq!(
+            observer.render(&forest),
+            "#####\n###.#\n##...\n..#.#\n.#.#."
+        );
     }
 }
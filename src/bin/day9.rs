@@ -1,74 +1,399 @@
-use anyhow::Result;
-use itertools::chain;
-use itertools::Itertools;
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
 use std::fs;
 
-fn head(input: &str) -> Vec<(isize, isize)> {
-    let (mut x, mut y) = (0_isize, 0_isize);
-    chain![
-        [(0, 0)],
-        input
-            .lines()
-            .map(|line| {
-                line.split_once(' ')
-                    .map(|(a, b)| (a, b.parse::<usize>().expect("parse error digit")))
-                    .expect("bad line")
-            })
-            .flat_map(|(dir, length)| {
-                let mut result = Vec::new();
-                for _ in 0..length {
-                    match dir {
-                        "L" => x -= 1,
-                        "R" => x += 1,
-                        "D" => y -= 1,
-                        "U" => y += 1,
-                        _ => panic!("parse error LRUD"),
-                    };
-                    result.push((x, y));
+/// x, y and z, with z always 0 for ropes in the plane
+type Point = [isize; 3];
+
+const ORIGIN: Point = [0; 3];
+
+/// Each line is a direction and how many steps to take in it. A direction is any mix of
+/// `L`/`R`, `D`/`U` and, for 3D ropes, `B`/`F`, so `UL` moves diagonally
+fn parse_moves(input: &str, dimensions: usize) -> Result<Vec<(Point, usize)>> {
+    input
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (dir, length) = line.split_once(' ').context("bad line")?;
+            let mut step = ORIGIN;
+            for c in dir.chars() {
+                let (axis, delta) = match c {
+                    'L' => (0, -1),
+                    'R' => (0, 1),
+                    'D' => (1, -1),
+                    'U' => (1, 1),
+                    'B' if dimensions == 3 => (2, -1),
+                    'F' if dimensions == 3 => (2, 1),
+                    _ => bail!("{dir:?} isn't a direction"),
+                };
+                if step[axis] != 0 {
+                    bail!("{dir:?} goes along the same axis twice");
                 }
-                result.into_iter()
-            })
-    ]
-    .collect()
-}
-
-fn tail(head: impl IntoIterator<Item = (isize, isize)>) -> Vec<(isize, isize)> {
-    chain![
-        [(0, 0)],
-        head.into_iter()
-            .scan((0, 0), |(t_x, t_y), (h_x, h_y)| {
-                Some(if h_x.abs_diff(*t_x).max(h_y.abs_diff(*t_y)) > 1 {
-                    *t_x += h_x.cmp(t_x) as isize;
-                    *t_y += h_y.cmp(t_y) as isize;
-                    Some((*t_x, *t_y))
-                } else {
-                    None
-                })
+                step[axis] = delta;
+            }
+            if step == ORIGIN {
+                bail!("bad line");
+            }
+            Ok((step, length.parse()?))
+        })
+        .collect()
+}
+
+/// How a knot decides it's too far from the one in front, and how it catches up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Follow {
+    /// diagonal neighbours are touching, and knots catch up diagonally
+    Chebyshev,
+    /// only knots in a straight line are touching, and knots catch up along one axis at
+    /// a time, closing the widest gap first (x before y before z when they're level)
+    Manhattan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Physics {
+    /// how far apart two knots can be before the one behind moves
+    slack: usize,
+    follow: Follow,
+    dimensions: usize,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            slack: 1,
+            follow: Follow::Chebyshev,
+            dimensions: 2,
+        }
+    }
+}
+
+impl Physics {
+    fn distance(&self, a: Point, b: Point) -> usize {
+        let gaps = (0..3).map(|axis| a[axis].abs_diff(b[axis]));
+        match self.follow {
+            Follow::Chebyshev => gaps.max().unwrap(),
+            Follow::Manhattan => gaps.sum(),
+        }
+    }
+
+    /// The next step `knot` takes towards `leader`, if it's too far behind. A step is a
+    /// single cell, or a diagonal when following by Chebyshev distance
+    fn follow(&self, leader: Point, knot: Point) -> Option<Point> {
+        if self.distance(leader, knot) <= self.slack {
+            return None;
+        }
+        let mut moved = knot;
+        match self.follow {
+            Follow::Chebyshev => {
+                for axis in 0..3 {
+                    moved[axis] += leader[axis].cmp(&knot[axis]) as isize;
+                }
+            }
+            Follow::Manhattan => {
+                let axis = (0..3)
+                    .rev()
+                    .max_by_key(|&axis| leader[axis].abs_diff(knot[axis]))
+                    .unwrap();
+                moved[axis] += leader[axis].cmp(&knot[axis]) as isize;
+            }
+        }
+        Some(moved)
+    }
+}
+
+/// The positions a knot has been in, each packed into a single `u128`
+#[derive(Debug, Default, Clone)]
+struct Trail(HashSet<u128>);
+
+impl Trail {
+    fn pack(point: Point) -> u128 {
+        point
+            .iter()
+            .fold(0, |key, &n| (key << 32) | n as i32 as u32 as u128)
+    }
+
+    fn unpack(key: u128) -> Point {
+        [64, 32, 0].map(|shift| (key >> shift) as u32 as i32 as isize)
+    }
+
+    fn insert(&mut self, point: Point) {
+        self.0.insert(Self::pack(point));
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Point> + '_ {
+        self.0.iter().map(|&key| Self::unpack(key))
+    }
+}
+
+/// A rope whose knots all move together, one step of the head at a time, keeping track
+/// of everywhere each knot has been
+#[derive(Debug, Clone)]
+struct Rope {
+    physics: Physics,
+    knots: Vec<Point>,
+    trails: Vec<Trail>,
+}
+
+impl Rope {
+    /// A rope of `length` knots, all starting at the origin
+    fn new(length: usize, physics: Physics) -> Self {
+        assert!(length > 0, "a rope needs at least one knot");
+        let mut trail = Trail::default();
+        trail.insert(ORIGIN);
+        Self {
+            physics,
+            knots: vec![ORIGIN; length],
+            trails: vec![trail; length],
+        }
+    }
+
+    /// Moves the head by `step`, and then each knot after it if it's come too far apart
+    /// from the one in front. Stops early once a knot doesn't need to move
+    fn step(&mut self, step: Point) {
+        for (n, delta) in self.knots[0].iter_mut().zip(step) {
+            *n += delta;
+        }
+        self.trails[0].insert(self.knots[0]);
+        // one step is enough to catch up with a Chebyshev leader, but a diagonal move
+        // can leave a Manhattan follower two or three steps behind
+        for i in 1..self.knots.len() {
+            let mut moved = false;
+            while let Some(next) = self.physics.follow(self.knots[i - 1], self.knots[i]) {
+                self.knots[i] = next;
+                self.trails[i].insert(next);
+                moved = true;
+            }
+            if !moved {
+                break;
+            }
+        }
+    }
+
+    fn run(&mut self, moves: &[(Point, usize)]) {
+        for &(step, length) in moves {
+            for _ in 0..length {
+                self.step(step);
+            }
+        }
+    }
+
+    fn tail(&self) -> Point {
+        *self.knots.last().unwrap()
+    }
+
+    /// Everywhere knot `i` has been, with the head as knot 0
+    fn trail(&self, i: usize) -> &Trail {
+        &self.trails[i]
+    }
+
+    /// `H` for the head, then `1` to `9`, with `T` for the tail of a two knot rope or
+    /// of one too long to number every knot. Any other knots past 9 are `+`
+    fn label(&self, i: usize) -> char {
+        let last = self.knots.len() - 1;
+        match i {
+            0 => 'H',
+            _ if i == last && (last == 1 || last > 9) => 'T',
+            1..=9 => char::from_digit(i as u32, 10).unwrap(),
+            _ => '+',
+        }
+    }
+}
+
+/// The part of the plane a picture of the rope covers, which is the same for every frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Viewport {
+    x: (isize, isize),
+    y: (isize, isize),
+}
+
+impl Viewport {
+    /// Just big enough for everywhere the head has been. The other knots only ever
+    /// follow it, so they never leave this box either
+    fn around(trail: &Trail) -> Self {
+        let bounds = |axis: usize| {
+            let along = || trail.iter().map(|point| point[axis]);
+            (along().min().unwrap(), along().max().unwrap())
+        };
+        Self {
+            x: bounds(0),
+            y: bounds(1),
+        }
+    }
+
+    /// Draws one character per point with y going up the page, the way the puzzle does.
+    /// Only x and y are drawn, so a 3D rope comes out flattened
+    fn draw(&self, mut symbol: impl FnMut(Point) -> char) -> String {
+        (self.y.0..=self.y.1)
+            .rev()
+            .map(|y| {
+                (self.x.0..=self.x.1)
+                    .map(|x| symbol([x, y, 0]))
+                    .collect::<String>()
             })
-            .flatten()
-    ]
-    .collect()
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// The rope with each knot labelled, the knots nearer the head drawn on top, and `s`
+/// for the start if nothing is covering it
+fn render(rope: &Rope, viewport: &Viewport) -> String {
+    viewport.draw(
+        |point| match rope.knots.iter().position(|knot| knot[..2] == point[..2]) {
+            Some(i) => rope.label(i),
+            None if point == ORIGIN => 's',
+            None => '.',
+        },
+    )
 }
 
-fn part1(input: &str) -> usize {
-    tail(head(input)).into_iter().unique().count()
+/// Everywhere a knot has been as `#`, with `s` for the start
+fn render_trail(trail: &Trail, viewport: &Viewport) -> String {
+    let flat = trail
+        .iter()
+        .map(|[x, y, _]| [x, y, 0])
+        .collect::<HashSet<_>>();
+    viewport.draw(|point| match point {
+        ORIGIN => 's',
+        _ if flat.contains(&point) => '#',
+        _ => '.',
+    })
 }
-fn part2(input: &str, length: usize) -> usize {
-    let mut link = head(input);
-    for _ in 1..length {
-        link = tail(link);
+
+/// Spells a step out the way the input does, like `U` or `UL`
+fn direction(step: Point) -> String {
+    [(0, 'L', 'R'), (1, 'D', 'U'), (2, 'B', 'F')]
+        .iter()
+        .filter_map(|&(axis, minus, plus)| match step[axis] {
+            -1 => Some(minus),
+            1 => Some(plus),
+            _ => None,
+        })
+        .rev()
+        .collect()
+}
+
+/// Runs the moves, handing a titled frame to `emit` before the first move and after
+/// every move, or after every single step if `every_step` is set
+fn animate(
+    moves: &[(Point, usize)],
+    mut rope: Rope,
+    every_step: bool,
+    mut emit: impl FnMut(&str, &str) -> Result<()>,
+) -> Result<Rope> {
+    let viewport = {
+        let mut rope = rope.clone();
+        rope.run(moves);
+        Viewport::around(rope.trail(0))
+    };
+    emit("Initial State", &render(&rope, &viewport))?;
+    for &(step, length) in moves {
+        let title = format!("{} {length}", direction(step));
+        for i in 0..length {
+            rope.step(step);
+            if every_step || i + 1 == length {
+                emit(&title, &render(&rope, &viewport))?;
+            }
+        }
     }
-    link.into_iter().unique().count()
+    Ok(rope)
+}
+
+fn part1(moves: &[(Point, usize)]) -> usize {
+    part2(moves, 2)
+}
+fn part2(moves: &[(Point, usize)], length: usize) -> usize {
+    let mut rope = Rope::new(length, Physics::default());
+    rope.run(moves);
+    rope.trail(length - 1).len()
 }
 fn main() -> Result<()> {
-    let input = fs::read_to_string("inputs/day9.txt")?;
+    let mut physics = Physics::default();
+    let mut knots = 10;
+    let mut path = "inputs/day9.txt".to_owned();
+    let (mut frames, mut every_step, mut frame_dir, mut show_trail) = (false, false, None, false);
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().context("missing value");
+        match arg.as_str() {
+            "--slack" => physics.slack = value()?.parse()?,
+            "--follow" => {
+                physics.follow = match value()?.as_str() {
+                    "chebyshev" => Follow::Chebyshev,
+                    "manhattan" => Follow::Manhattan,
+                    other => bail!("{other:?} isn't chebyshev or manhattan"),
+                }
+            }
+            "--3d" => physics.dimensions = 3,
+            "--knots" => knots = value()?.parse()?,
+            "--input" => path = value()?.clone(),
+            "--animate" => frames = true,
+            "--every-step" => (frames, every_step) = (true, true),
+            "--frames" => (frames, frame_dir) = (true, Some(value()?.clone())),
+            "--trail" => show_trail = true,
+            _ => bail!(
+                "usage: day9 [--slack N] [--follow chebyshev|manhattan] [--3d] [--knots N] \
+                 [--input FILE] [--animate] [--every-step] [--frames DIR] [--trail]"
+            ),
+        }
+    }
+    if knots == 0 {
+        bail!("a rope needs at least one knot");
+    }
+
+    let input = fs::read_to_string(&path)?;
+    let moves = parse_moves(&input, physics.dimensions)?;
     let time = std::time::Instant::now();
-    println!("9.1 {}", part1(&input));
+    println!("9.1 {}", part1(&moves));
     println!("{:?}", time.elapsed());
     let time = std::time::Instant::now();
-    println!("9.2 {}", part2(&input, 10));
+    println!("9.2 {}", part2(&moves, 10));
     println!("{:?}", time.elapsed());
 
+    let mut rope = Rope::new(knots, physics);
+    if frames {
+        if let Some(dir) = &frame_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let mut count = 0;
+        rope = animate(&moves, rope, every_step, |title, frame| {
+            count += 1;
+            let text = format!("== {title} ==\n\n{frame}\n");
+            match &frame_dir {
+                Some(dir) => fs::write(format!("{dir}/frame-{count:05}.txt"), text)?,
+                None => println!("{text}"),
+            }
+            Ok(())
+        })?;
+        if let Some(dir) = &frame_dir {
+            println!("wrote {count} frames to {dir}");
+        }
+    } else {
+        rope.run(&moves);
+    }
+    if show_trail {
+        let viewport = Viewport::around(rope.trail(0));
+        println!("{}", render_trail(rope.trail(knots - 1), &viewport));
+    }
+    let visited = (0..knots).map(|i| rope.trail(i).len()).collect::<Vec<_>>();
+    println!("places visited by each knot: {visited:?}");
+    let bounds = (0..physics.dimensions)
+        .map(|axis| {
+            let along = || rope.trail(knots - 1).iter().map(|point| point[axis]);
+            format!("{}..={}", along().min().unwrap(), along().max().unwrap())
+        })
+        .collect::<Vec<_>>();
+    println!(
+        "the tail ended up at {:?}, having wandered over {}",
+        &rope.tail()[..physics.dimensions],
+        bounds.join(" by ")
+    );
+
     Ok(())
 }
 
@@ -80,15 +405,221 @@ mod tests {
     fn test_part1() {
         let input = fs::read_to_string("test_inputs/day9.txt").unwrap();
 
-        assert_eq!(part1(&input), 13);
+        assert_eq!(part1(&parse_moves(&input, 2).unwrap()), 13);
     }
     #[test]
     fn test_part2() {
         let input = fs::read_to_string("test_inputs/day9.txt").unwrap();
 
-        assert_eq!(part2(&input, 10), 1);
+        assert_eq!(part2(&parse_moves(&input, 2).unwrap(), 10), 1);
+        let input = fs::read_to_string("test_inputs/day9-2.txt").unwrap();
+
+        assert_eq!(part2(&parse_moves(&input, 2).unwrap(), 10), 36);
+    }
+
+    #[test]
+    fn test_rope() {
         let input = fs::read_to_string("test_inputs/day9-2.txt").unwrap();
+        let mut rope = Rope::new(10, Physics::default());
+        rope.run(&parse_moves(&input, 2).unwrap());
+        assert_eq!(rope.knots[0], [-11, 15, 0]);
+        assert_eq!(rope.tail(), [-11, 6, 0]);
+        // the knots further down the rope have been to fewer places
+        let lengths = (0..10).map(|i| rope.trail(i).len()).collect::<Vec<_>>();
+        assert!(lengths.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(rope.trail(9).iter().any(|point| point == [-11, 6, 0]));
+        assert!(rope
+            .trail(9)
+            .iter()
+            .all(|[x, y, _]| x.abs() <= 11 && y.abs() <= 6));
 
-        assert_eq!(part2(&input, 10), 36);
+        assert!(parse_moves("X 3", 2).is_err());
+        assert!(parse_moves("U three", 2).is_err());
+    }
+
+    #[test]
+    fn test_trail_packing() {
+        for point in [
+            [0, 0, 0],
+            [-1, 1, 2],
+            [i32::MIN as isize, i32::MAX as isize, -7],
+        ] {
+            assert_eq!(Trail::unpack(Trail::pack(point)), point);
+        }
+    }
+
+    #[test]
+    fn test_parse_directions() {
+        assert_eq!(
+            parse_moves("UL 2\nDR 1\nR 3", 2).unwrap(),
+            vec![([-1, 1, 0], 2), ([1, -1, 0], 1), ([1, 0, 0], 3)]
+        );
+        assert_eq!(parse_moves("UFL 1", 3).unwrap(), vec![([-1, 1, 1], 1)]);
+        assert!(parse_moves("F 1", 2).is_err());
+        assert!(parse_moves("LR 1", 2).is_err());
+        assert!(parse_moves("UU 1", 2).is_err());
+    }
+
+    #[test]
+    fn test_physics() {
+        let chebyshev = Physics::default();
+        assert_eq!(chebyshev.follow([1, 1, 0], ORIGIN), None);
+        assert_eq!(chebyshev.follow([2, 1, 0], ORIGIN), Some([1, 1, 0]));
+
+        let manhattan = Physics {
+            follow: Follow::Manhattan,
+            ..chebyshev
+        };
+        assert_eq!(manhattan.follow([1, 0, 0], ORIGIN), None);
+        assert_eq!(manhattan.follow([1, 1, 0], ORIGIN), Some([1, 0, 0]));
+        assert_eq!(manhattan.follow([1, 2, 0], ORIGIN), Some([0, 1, 0]));
+        assert_eq!(manhattan.follow([2, 1, 0], ORIGIN), Some([1, 0, 0]));
+
+        // diagonal moves pull a Manhattan rope along without stretching it
+        let mut rope = Rope::new(2, manhattan);
+        rope.run(&parse_moves("UR 10", 2).unwrap());
+        assert_eq!(rope.knots, vec![[10, 10, 0], [10, 9, 0]]);
+        assert_eq!(rope.trail(1).len(), 20);
+        let mut rope = Rope::new(
+            3,
+            Physics {
+                dimensions: 3,
+                ..manhattan
+            },
+        );
+        rope.run(&parse_moves("UFR 4\nDL 2", 3).unwrap());
+        assert_eq!(rope.knots[0], [2, 2, 4]);
+        for pair in rope.knots.windows(2) {
+            assert_eq!(manhattan.distance(pair[0], pair[1]), 1);
+        }
+
+        let slack = Physics {
+            slack: 3,
+            ..chebyshev
+        };
+        assert_eq!(slack.follow([3, -3, 0], ORIGIN), None);
+        assert_eq!(slack.follow([4, -1, 0], ORIGIN), Some([1, -1, 0]));
+
+        // a straight pull keeps a slack rope as long as the slack allows
+        let mut rope = Rope::new(3, slack);
+        rope.run(&[([1, 0, 0], 10)]);
+        assert_eq!(rope.knots, vec![[10, 0, 0], [7, 0, 0], [4, 0, 0]]);
+        assert_eq!(rope.trail(2).len(), 5);
+
+        let mut rope = Rope::new(
+            2,
+            Physics {
+                dimensions: 3,
+                ..chebyshev
+            },
+        );
+        rope.run(&parse_moves("UFR 3\nB 2", 3).unwrap());
+        assert_eq!(rope.knots, vec![[3, 3, 1], [2, 2, 2]]);
+        assert_eq!(rope.trail(1).len(), 3);
+    }
+
+    #[test]
+    fn test_render() {
+        let input = fs::read_to_string("test_inputs/day9-2.txt").unwrap();
+        let moves = parse_moves(&input, 2).unwrap();
+        let mut frames = vec![];
+        let rope = animate(
+            &moves,
+            Rope::new(10, Physics::default()),
+            false,
+            |title, frame| {
+                frames.push((title.to_owned(), frame.to_owned()));
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(frames.len(), moves.len() + 1);
+        assert_eq!(frames[0].0, "Initial State");
+        assert_eq!(frames[1].0, "R 5");
+        // the puzzle's first frame has the head five steps right of the start
+        assert_eq!(
+            frames[1].1.lines().nth(15).unwrap(),
+            "...........54321H........."
+        );
+        assert_eq!(frames[1].1.lines().count(), 21);
+        let last = frames.last().unwrap();
+        assert_eq!(last.0, "U 20");
+        assert_eq!(
+            last.1,
+            "H.........................
+1.........................
+2.........................
+3.........................
+4.........................
+5.........................
+6.........................
+7.........................
+8.........................
+9.........................
+..........................
+..........................
+..........................
+..........................
+..........................
+...........s..............
+..........................
+..........................
+..........................
+..........................
+.........................."
+        );
+
+        let viewport = Viewport::around(rope.trail(0));
+        assert_eq!(
+            render_trail(rope.trail(9), &viewport),
+            "..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+..........................
+#.........................
+#.............###.........
+#............#...#........
+.#..........#.....#.......
+..#..........#.....#......
+...#........#.......#.....
+....#......s.........#....
+.....#..............#.....
+......#............#......
+.......#..........#.......
+........#........#........
+.........########........."
+        );
+    }
+
+    #[test]
+    fn test_small_frames() {
+        let input = fs::read_to_string("test_inputs/day9.txt").unwrap();
+        let moves = parse_moves(&input, 2).unwrap();
+        let mut frames = vec![];
+        let rope = animate(
+            &moves,
+            Rope::new(2, Physics::default()),
+            true,
+            |_, frame| {
+                frames.push(frame.to_owned());
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(frames.len(), 25);
+        assert_eq!(frames[0], "......\n......\n......\n......\nH.....");
+        assert_eq!(frames[1], "......\n......\n......\n......\nTH....");
+        assert_eq!(frames[2], "......\n......\n......\n......\nsTH...");
+        assert_eq!(
+            render_trail(rope.trail(1), &Viewport::around(rope.trail(0))),
+            "..##..\n...##.\n.####.\n....#.\ns###.."
+        );
+        assert_eq!(direction([-1, 1, 0]), "UL");
+        assert_eq!(direction([1, -1, 1]), "FDR");
     }
 }